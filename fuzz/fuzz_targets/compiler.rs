@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use m2_rslox::{debug, Compiler};
+
+fuzz_target!(|source: &str| {
+    if let Ok(chunk) = Compiler::new(source).compile() {
+        // Everything the compiler emits must also disassemble
+        debug::chunk_to_string(&chunk, "fuzz");
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    // Every call to scan_token consumes input, so the token count is
+    // bounded by the source length.
+    let tokens = m2_rslox::fuzzing::scan(source);
+    assert!(tokens <= source.len());
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use m2_rslox::{InterpretResult, VM};
+
+const FUEL: usize = 10_000;
+
+fuzz_target!(|source: &str| {
+    let mut vm = VM::builder()
+        .fuel(FUEL)
+        .output(std::io::sink())
+        .error_output(std::io::sink())
+        .build();
+
+    // Compile and runtime errors are fine, the VM itself must never fail
+    let result = vm.interpret(source);
+    assert_ne!(result, Err(InterpretResult::InternalError));
+});
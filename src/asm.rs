@@ -0,0 +1,356 @@
+//! Assembler for the textual bytecode format produced by the disassembler.
+//!
+//! Each instruction line has the form
+//!
+//! ```text
+//! [offset] [line|'|'] Mnemonic[(operand)] [constant]
+//! ```
+//!
+//! so the output of `debug::write_chunk` assembles back into the same chunk.
+//! Hand-written code may leave out the offset and line columns (a missing
+//! line repeats the previous one) and may write constants as
+//! `Constant Number(1.5)` or `GetGlobal String("x")`, which appends a new
+//! entry to the constant table. Function constants can't be written, so
+//! only code without function declarations assembles.
+//! `== name ==` headers, blank lines and `;;` comments are ignored.
+//!
+//! Assembled chunks aren't verified, so check them with
+//! [`Chunk::verify`](crate::Chunk::verify) before running them.
+
+use std::fmt;
+
+use crate::chunk::{Chunk, OpCode};
+use crate::value::Value;
+
+#[derive(Debug)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+enum Pending {
+    Op(OpCode),
+    /// An instruction referencing a constant, with the constant's explicit
+    /// table index if one was given.
+    Constant(fn(u8) -> OpCode, Option<u8>, Value),
+}
+
+struct Assembler {
+    code: Vec<(Pending, usize)>,
+    constants: Vec<Option<Value>>,
+    text_line: usize,
+}
+
+pub fn assemble(text: &str) -> Result<Chunk, AssembleError> {
+    let mut asm = Assembler {
+        code: Vec::new(),
+        constants: Vec::new(),
+        text_line: 0,
+    };
+
+    let mut source_line = 1;
+    for (idx, line) in text.lines().enumerate() {
+        asm.text_line = idx + 1;
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(";;") || line.starts_with("==") {
+            continue;
+        }
+
+        source_line = asm.instruction(line, source_line)?;
+    }
+
+    asm.finish()
+}
+
+impl Assembler {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AssembleError> {
+        Err(AssembleError { line: self.text_line, message: message.into() })
+    }
+
+    fn byte_operand(&self, name: &str, operand: Option<u16>) -> Result<Option<u8>, AssembleError> {
+        match operand.map(u8::try_from) {
+            None => Ok(None),
+            Some(Ok(operand)) => Ok(Some(operand)),
+            Some(Err(_)) => self.error(format!("Operand of '{}' must fit in a byte", name)),
+        }
+    }
+
+    /// Parses one instruction line, returning the source line it belongs to.
+    fn instruction(&mut self, text: &str, previous_line: usize) -> Result<usize, AssembleError> {
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+
+        let is_column = |word: &str| word == "|" || word.chars().all(|c| c.is_ascii_digit());
+        let columns = words.iter().take(2).take_while(|w| is_column(w)).count();
+        let line_column = match columns {
+            0 => None,
+            1 => Some(words[0]),
+            _ => Some(words[1]),
+        };
+        words.drain(..columns);
+
+        let line = match line_column {
+            None | Some("|") => previous_line,
+            Some(number) => match number.parse() {
+                Ok(line) => line,
+                Err(_) => return self.error(format!("Invalid line number '{}'", number)),
+            },
+        };
+
+        let Some((&mnemonic, rest)) = words.split_first() else {
+            return self.error("Expected an instruction");
+        };
+
+        let (name, operand) = match mnemonic.split_once('(') {
+            Some((name, arg)) => match arg.strip_suffix(')').map(str::parse::<u16>) {
+                Some(Ok(operand)) => (name, Some(operand)),
+                _ => return self.error(format!("Invalid operand in '{}'", mnemonic)),
+            },
+            None => (mnemonic, None),
+        };
+
+        let pending = if let Some(make_op) = constant_opcode(name) {
+            let index = self.byte_operand(name, operand)?;
+            // Taken from the text rather than the words so whitespace inside
+            // strings survives
+            let value_text = text[text.find(mnemonic).unwrap() + mnemonic.len()..].trim();
+            let Some(value) = parse_value(value_text) else {
+                return self.error(format!("Expected a constant value after '{}'", name));
+            };
+            Pending::Constant(make_op, index, value)
+        }
+        else if let Some(make_op) = byte_opcode(name) {
+            let Some(byte) = self.byte_operand(name, operand)? else {
+                return self.error(format!("'{}' needs an operand", name));
+            };
+            if !rest.is_empty() {
+                return self.error(format!("'{}' takes a single operand", name));
+            }
+            Pending::Op(make_op(byte))
+        }
+        else if let Some(make_op) = jump_opcode(name) {
+            let Some(distance) = operand else {
+                return self.error(format!("'{}' needs a distance operand", name));
+            };
+            let op = make_op(distance);
+
+            // The disassembler appends the target as `-> offset`
+            match rest {
+                [] => {},
+                ["->", target] => {
+                    let offset = self.code.len();
+                    if target.parse().ok() != op.jump_target(offset) {
+                        return self.error(format!("'{}' at {:04} does not jump to {}", mnemonic, offset, target));
+                    }
+                },
+                _ => return self.error(format!("Expected '-> offset' after '{}'", mnemonic)),
+            }
+            Pending::Op(op)
+        }
+        else {
+            let Some(op) = simple_opcode(name) else {
+                return self.error(format!("Unknown instruction '{}'", name));
+            };
+            if operand.is_some() || !rest.is_empty() {
+                return self.error(format!("'{}' takes no operands", name));
+            }
+            Pending::Op(op)
+        };
+
+        if let Pending::Constant(_, Some(index), value) = &pending {
+            let index = *index as usize;
+            if self.constants.len() <= index {
+                self.constants.resize(index + 1, None);
+            }
+            match &self.constants[index] {
+                Some(existing) if !same_value(existing, value) => {
+                    return self.error(format!("Constant {} redefined with a different value", index));
+                },
+                _ => self.constants[index] = Some(value.clone()),
+            }
+        }
+
+        self.code.push((pending, line));
+        Ok(line)
+    }
+
+    fn finish(mut self) -> Result<Chunk, AssembleError> {
+        // Constants without an explicit index are appended after the
+        // explicitly numbered ones.
+        let mut code = Vec::new();
+        for (pending, line) in std::mem::take(&mut self.code) {
+            let op = match pending {
+                Pending::Op(op) => op,
+                Pending::Constant(make_op, Some(index), _) => make_op(index),
+                Pending::Constant(make_op, None, value) => {
+                    if self.constants.len() > u8::MAX as usize {
+                        return self.error("Too many constants in one chunk");
+                    }
+                    self.constants.push(Some(value));
+                    make_op((self.constants.len() - 1) as u8)
+                },
+            };
+            code.push((op, line));
+        }
+
+        let mut chunk = Chunk::new();
+        for (index, constant) in std::mem::take(&mut self.constants).into_iter().enumerate() {
+            match constant {
+                Some(value) => { chunk.add_constant(value); },
+                None => return self.error(format!("Constant {} is never defined", index)),
+            }
+        }
+        for (op, line) in code {
+            chunk.write(op, line);
+        }
+        Ok(chunk)
+    }
+}
+
+/// Instructions whose operand is a constant, by name.
+fn constant_opcode(name: &str) -> Option<fn(u8) -> OpCode> {
+    let make_op = match name {
+        "Constant" => OpCode::Constant,
+        "DefineGlobal" => OpCode::DefineGlobal,
+        "GetGlobal" => OpCode::GetGlobal,
+        "SetGlobal" => OpCode::SetGlobal,
+        "Import" => OpCode::Import,
+        _ => return None,
+    };
+    Some(make_op)
+}
+
+/// Instructions whose operand is a local's stack slot or a count of values,
+/// by name.
+fn byte_opcode(name: &str) -> Option<fn(u8) -> OpCode> {
+    let make_op = match name {
+        "GetLocal" => OpCode::GetLocal,
+        "SetLocal" => OpCode::SetLocal,
+        "Call" => OpCode::Call,
+        "BuildList" => OpCode::BuildList,
+        "BuildMap" => OpCode::BuildMap,
+        "Unpack" => OpCode::Unpack,
+        "UnpackMap" => OpCode::UnpackMap,
+        _ => return None,
+    };
+    Some(make_op)
+}
+
+/// Instructions whose operand is a jump distance, by name.
+fn jump_opcode(name: &str) -> Option<fn(u16) -> OpCode> {
+    let make_op = match name {
+        "Jump" => OpCode::Jump,
+        "JumpIfFalse" => OpCode::JumpIfFalse,
+        "Loop" => OpCode::Loop,
+        "ForIter" => OpCode::ForIter,
+        "PushHandler" => OpCode::PushHandler,
+        _ => return None,
+    };
+    Some(make_op)
+}
+
+/// Instructions that don't take an operand.
+fn simple_opcode(name: &str) -> Option<OpCode> {
+    let op = match name {
+        "Nil" => OpCode::Nil,
+        "True" => OpCode::True,
+        "False" => OpCode::False,
+        "Pop" => OpCode::Pop,
+        "Equal" => OpCode::Equal,
+        "Greater" => OpCode::Greater,
+        "GreaterEqual" => OpCode::GreaterEqual,
+        "Less" => OpCode::Less,
+        "LessEqual" => OpCode::LessEqual,
+        "Add" => OpCode::Add,
+        "Substract" => OpCode::Substract,
+        "Multiply" => OpCode::Multiply,
+        "Divide" => OpCode::Divide,
+        "Modulo" => OpCode::Modulo,
+        "BitAnd" => OpCode::BitAnd,
+        "BitOr" => OpCode::BitOr,
+        "BitXor" => OpCode::BitXor,
+        "ShiftLeft" => OpCode::ShiftLeft,
+        "ShiftRight" => OpCode::ShiftRight,
+        "BitNot" => OpCode::BitNot,
+        "Not" => OpCode::Not,
+        "Negate" => OpCode::Negate,
+        "Print" => OpCode::Print,
+        "GetIndex" => OpCode::GetIndex,
+        "SetIndex" => OpCode::SetIndex,
+        "Slice" => OpCode::Slice,
+        "PopHandler" => OpCode::PopHandler,
+        "Throw" => OpCode::Throw,
+        "Range" => OpCode::Range,
+        "Return" => OpCode::Return,
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// Parses a value in its `Debug` representation, e.g. `Number(1.5)`.
+fn parse_value(text: &str) -> Option<Value> {
+    if text == "Nil" {
+        return Some(Value::Nil);
+    }
+
+    let (kind, inner) = text.split_once('(')?;
+    let inner = inner.strip_suffix(')')?;
+
+    match kind {
+        "Bool" => inner.parse().ok().map(Value::Bool),
+        "Number" => inner.parse().ok().map(Value::Number),
+        "Int" => inner.parse().ok().map(Value::Int),
+        "String" => parse_string_literal(inner).map(|string| Value::String(string.into())),
+        _ => None,
+    }
+}
+
+/// Parses a quoted string with the escapes produced by `Debug` for `str`.
+fn parse_string_literal(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '"' {
+            return None;
+        }
+        if ch != '\\' {
+            string.push(ch);
+            continue;
+        }
+
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            ch @ ('\\' | '"' | '\'') => ch,
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let (hex, rest) = rest.split_once('}')?;
+                chars = rest.chars();
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            },
+            _ => return None,
+        };
+        string.push(escaped);
+    }
+    Some(string)
+}
+
+/// Constant identity for the assembler, where NaN must match itself.
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
@@ -0,0 +1,85 @@
+//! Compiling many files in one go, in parallel.
+//!
+//! Each file is compiled on its own and reported separately, so one broken
+//! file doesn't hide the diagnostics of the others. Compiled files are
+//! returned in the [`bytecode`](crate::bytecode) format ready to be written
+//! out as `.loxc` files.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::bytecode;
+use crate::compiler::{Compiler, CompilerOptions};
+
+pub struct CompiledFile {
+    pub path: PathBuf,
+    /// The encoded chunk, or the diagnostics explaining why there is none.
+    pub result: Result<Vec<u8>, Vec<String>>,
+}
+
+impl CompiledFile {
+    /// Where the compiled file goes: next to the source with a `.loxc`
+    /// extension.
+    pub fn output_path(&self) -> PathBuf {
+        self.path.with_extension("loxc")
+    }
+}
+
+/// Compiles every file in `paths`, returning the results in the same order.
+pub fn compile_files(paths: &[PathBuf], options: &CompilerOptions) -> Vec<CompiledFile> {
+    paths.par_iter()
+        .map(|path| CompiledFile {
+            path: path.clone(),
+            result: compile_file(path, options),
+        })
+        .collect()
+}
+
+fn compile_file(path: &Path, options: &CompilerOptions) -> Result<Vec<u8>, Vec<String>> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| vec![format!("Could not read file: {}", err)])?;
+
+    let mut options = options.clone();
+    // Disassembling from several threads at once would interleave the output
+    options.print_code = false;
+
+    match Compiler::with_options(&source, options).compile() {
+        Ok(chunk) => Ok(bytecode::encode(&chunk)),
+        Err(err) => Err(err.diagnostics),
+    }
+}
+
+/// Expands directories in `paths` into the `.lox` files they contain,
+/// recursively and in sorted order. Other paths are kept as given.
+pub fn collect_sources(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_dir(path, &mut sources)?;
+        }
+        else {
+            sources.push(path.clone());
+        }
+    }
+    Ok(sources)
+}
+
+fn collect_dir(dir: &Path, sources: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_dir(&path, sources)?;
+        }
+        else if path.extension().is_some_and(|ext| ext == "lox") {
+            sources.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,337 @@
+//! Binary encoding of compiled chunks, used for `.loxc` files and the chunk
+//! cache.
+//!
+//! The layout is little endian throughout:
+//!
+//! ```text
+//! "LOXC" version:u8 chunk
+//!
+//! chunk: constant_count:u32 (tag:u8 payload)*
+//!        instruction_count:u32 (opcode:u8 [operand] line:u32)*
+//!     payload: nil: none, bool: u8, number: f64, int: i64,
+//!              string: length:u32 utf8_bytes,
+//!              function: arity:u8 variadic:u8 name_length:u32 utf8_bytes chunk
+//!     operand: u8 constant index, local slot or count of values,
+//!              u16 jump distance
+//! ```
+//!
+//! Decoded chunks are verified before being returned, so they are safe to
+//! hand to the VM.
+
+use std::fmt;
+
+use std::rc::Rc;
+
+use crate::chunk::{Chunk, OpCode, VerifyError, MAX_FUNCTION_DEPTH};
+use crate::object::Function;
+use crate::value::Value;
+
+const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bumped whenever the layout or the meaning of an opcode changes.
+pub const FORMAT_VERSION: u8 = 17;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidConstantTag(u8),
+    InvalidString,
+    InvalidOpcode(u8),
+    /// Functions are nested deeper than [`MAX_FUNCTION_DEPTH`].
+    TooDeep,
+    Invalid(VerifyError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("Not a compiled Lox chunk"),
+            Self::UnsupportedVersion(version) => write!(
+                f, "Unsupported bytecode version {} (expected {})", version, FORMAT_VERSION
+            ),
+            Self::UnexpectedEnd => f.write_str("Unexpected end of bytecode"),
+            Self::TrailingBytes => f.write_str("Trailing bytes after bytecode"),
+            Self::InvalidConstantTag(tag) => write!(f, "Invalid constant tag {}", tag),
+            Self::InvalidString => f.write_str("String constant is not valid UTF-8"),
+            Self::InvalidOpcode(op) => write!(f, "Invalid opcode {}", op),
+            Self::TooDeep => write!(f, "Functions nested more than {} deep", MAX_FUNCTION_DEPTH),
+            Self::Invalid(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Panics if the constant table holds a native function, a list or a map,
+/// which compiled code never does.
+pub fn encode(chunk: &Chunk) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    encode_chunk(&mut bytes, chunk);
+    bytes
+}
+
+fn encode_chunk(bytes: &mut Vec<u8>, chunk: &Chunk) {
+    bytes.extend_from_slice(&(chunk.constants().len() as u32).to_le_bytes());
+    for constant in chunk.constants() {
+        match constant {
+            Value::Nil => bytes.push(0),
+            Value::Bool(val) => bytes.extend_from_slice(&[1, *val as u8]),
+            Value::Number(val) => {
+                bytes.push(2);
+                bytes.extend_from_slice(&val.to_le_bytes());
+            },
+            Value::Int(val) => {
+                bytes.push(3);
+                bytes.extend_from_slice(&val.to_le_bytes());
+            },
+            Value::String(val) => {
+                bytes.push(4);
+                bytes.extend_from_slice(&(val.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(val.as_bytes());
+            },
+            Value::Function(function) => {
+                // Functions always have a name, only the script doesn't
+                let name = function.name.as_deref().unwrap_or_default();
+                bytes.extend_from_slice(&[5, function.arity, function.variadic as u8]);
+                bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(name.as_bytes());
+                encode_chunk(bytes, &function.chunk);
+            },
+            Value::NativeFn(_) => panic!("Native functions can't be encoded"),
+            Value::List(_) | Value::Map(_) | Value::Range { .. } => {
+                panic!("Lists, maps and ranges can't be encoded")
+            },
+        }
+    }
+
+    bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    for (_, op, line) in chunk.instructions() {
+        bytes.push(opcode_byte(op));
+        match op {
+            OpCode::Jump(distance) | OpCode::JumpIfFalse(distance) |
+            OpCode::Loop(distance) | OpCode::ForIter(distance) |
+            OpCode::PushHandler(distance) => {
+                bytes.extend_from_slice(&distance.to_le_bytes());
+            },
+            _ => {
+                if let [operand] = op.operands()[..] {
+                    bytes.push(operand as u8);
+                }
+            },
+        }
+        bytes.extend_from_slice(&(line as u32).to_le_bytes());
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Chunk, DecodeError> {
+    let mut reader = Reader { bytes };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = reader.byte()?;
+    if version != FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let chunk = decode_chunk(&mut reader, 0)?;
+
+    if !reader.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+
+    chunk.verify().map_err(DecodeError::Invalid)?;
+    Ok(chunk)
+}
+
+/// Decodes a chunk nested `depth` functions deep along with the functions
+/// in its constant table. Left unverified, `Chunk::verify` checks the
+/// functions too.
+fn decode_chunk(reader: &mut Reader, depth: usize) -> Result<Chunk, DecodeError> {
+    if depth > MAX_FUNCTION_DEPTH {
+        return Err(DecodeError::TooDeep);
+    }
+    let mut chunk = Chunk::new();
+
+    for _ in 0..reader.u32()? {
+        let value = match reader.byte()? {
+            0 => Value::Nil,
+            1 => Value::Bool(reader.byte()? != 0),
+            2 => Value::Number(f64::from_le_bytes(reader.array()?)),
+            3 => Value::Int(i64::from_le_bytes(reader.array()?)),
+            4 => Value::String(reader.string()?.into()),
+            5 => {
+                let arity = reader.byte()?;
+                let variadic = reader.byte()? != 0;
+                let name = reader.string()?;
+                let function = Function {
+                    arity,
+                    variadic,
+                    chunk: decode_chunk(reader, depth + 1)?,
+                    ..Function::new((!name.is_empty()).then(|| name.into()))
+                };
+                Value::Function(Rc::new(function))
+            },
+            tag => return Err(DecodeError::InvalidConstantTag(tag)),
+        };
+        chunk.add_constant(value);
+    }
+
+    for _ in 0..reader.u32()? {
+        let op = match reader.byte()? {
+            0 => OpCode::Constant(reader.byte()?),
+            16 => OpCode::DefineGlobal(reader.byte()?),
+            17 => OpCode::GetGlobal(reader.byte()?),
+            18 => OpCode::SetGlobal(reader.byte()?),
+            44 => OpCode::Import(reader.byte()?),
+            21 => OpCode::GetLocal(reader.byte()?),
+            22 => OpCode::SetLocal(reader.byte()?),
+            23 => OpCode::Jump(reader.u16()?),
+            24 => OpCode::JumpIfFalse(reader.u16()?),
+            25 => OpCode::Loop(reader.u16()?),
+            46 => OpCode::ForIter(reader.u16()?),
+            41 => OpCode::PushHandler(reader.u16()?),
+            26 => OpCode::Call(reader.byte()?),
+            34 => OpCode::BuildList(reader.byte()?),
+            35 => OpCode::BuildMap(reader.byte()?),
+            39 => OpCode::Unpack(reader.byte()?),
+            40 => OpCode::UnpackMap(reader.byte()?),
+            byte => simple_opcode(byte).ok_or(DecodeError::InvalidOpcode(byte))?,
+        };
+        let line = reader.u32()? as usize;
+        chunk.write(op, line);
+    }
+
+    Ok(chunk)
+}
+
+fn opcode_byte(op: &OpCode) -> u8 {
+    match op {
+        OpCode::Constant(_) => 0,
+        OpCode::Nil => 1,
+        OpCode::True => 2,
+        OpCode::False => 3,
+        OpCode::Equal => 4,
+        OpCode::Greater => 5,
+        OpCode::GreaterEqual => 6,
+        OpCode::Less => 7,
+        OpCode::LessEqual => 8,
+        OpCode::Add => 9,
+        OpCode::Substract => 10,
+        OpCode::Multiply => 11,
+        OpCode::Divide => 12,
+        OpCode::Not => 13,
+        OpCode::Negate => 14,
+        OpCode::Return => 15,
+        OpCode::DefineGlobal(_) => 16,
+        OpCode::GetGlobal(_) => 17,
+        OpCode::SetGlobal(_) => 18,
+        OpCode::Pop => 19,
+        OpCode::Print => 20,
+        OpCode::GetLocal(_) => 21,
+        OpCode::SetLocal(_) => 22,
+        OpCode::Jump(_) => 23,
+        OpCode::JumpIfFalse(_) => 24,
+        OpCode::Loop(_) => 25,
+        OpCode::Call(_) => 26,
+        OpCode::Modulo => 27,
+        OpCode::BitAnd => 28,
+        OpCode::BitOr => 29,
+        OpCode::BitXor => 30,
+        OpCode::ShiftLeft => 31,
+        OpCode::ShiftRight => 32,
+        OpCode::BitNot => 33,
+        OpCode::BuildList(_) => 34,
+        OpCode::BuildMap(_) => 35,
+        OpCode::GetIndex => 36,
+        OpCode::SetIndex => 37,
+        OpCode::Slice => 38,
+        OpCode::Unpack(_) => 39,
+        OpCode::UnpackMap(_) => 40,
+        OpCode::PushHandler(_) => 41,
+        OpCode::PopHandler => 42,
+        OpCode::Throw => 43,
+        OpCode::Import(_) => 44,
+        OpCode::Range => 45,
+        OpCode::ForIter(_) => 46,
+    }
+}
+
+/// Opcodes without operands, by their encoding.
+fn simple_opcode(byte: u8) -> Option<OpCode> {
+    let op = match byte {
+        1 => OpCode::Nil,
+        2 => OpCode::True,
+        3 => OpCode::False,
+        4 => OpCode::Equal,
+        5 => OpCode::Greater,
+        6 => OpCode::GreaterEqual,
+        7 => OpCode::Less,
+        8 => OpCode::LessEqual,
+        9 => OpCode::Add,
+        10 => OpCode::Substract,
+        11 => OpCode::Multiply,
+        12 => OpCode::Divide,
+        13 => OpCode::Not,
+        14 => OpCode::Negate,
+        15 => OpCode::Return,
+        19 => OpCode::Pop,
+        20 => OpCode::Print,
+        27 => OpCode::Modulo,
+        28 => OpCode::BitAnd,
+        29 => OpCode::BitOr,
+        30 => OpCode::BitXor,
+        31 => OpCode::ShiftLeft,
+        32 => OpCode::ShiftRight,
+        33 => OpCode::BitNot,
+        36 => OpCode::GetIndex,
+        37 => OpCode::SetIndex,
+        38 => OpCode::Slice,
+        42 => OpCode::PopHandler,
+        43 => OpCode::Throw,
+        45 => OpCode::Range,
+        _ => return None,
+    };
+    Some(op)
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, count: usize) -> Result<&'b [u8], DecodeError> {
+        if self.bytes.len() < count {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<&'b str, DecodeError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| DecodeError::InvalidString)
+    }
+}
@@ -0,0 +1,139 @@
+//! On-disk cache of compiled chunks, keyed by a hash of the source.
+//!
+//! Each entry holds the source it was compiled from followed by the chunk in
+//! the [`bytecode`](crate::bytecode) format, which is verified when loaded.
+//! The source is compared on load, so two sources with the same hash never
+//! share an entry. The least recently used entries are removed once there
+//! are more than [`ChunkCache::max_entries`]. The cache is best effort:
+//! unreadable, corrupt or stale entries are treated as misses and write
+//! failures are ignored.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::bytecode;
+use crate::chunk::Chunk;
+use crate::compiler::CompilerOptions;
+
+/// Entries kept by a cache unless told otherwise.
+pub const DEFAULT_MAX_ENTRIES: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct ChunkCache {
+    dir: PathBuf,
+    max_entries: usize,
+}
+
+impl ChunkCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), max_entries: DEFAULT_MAX_ENTRIES }
+    }
+
+    /// The per-user cache directory, `$XDG_CACHE_HOME/m2-rslox` or
+    /// `~/.cache/m2-rslox`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(base.join("m2-rslox"))
+    }
+
+    /// Keeps at most `max_entries` entries, removing the least recently used
+    /// ones when storing a new entry goes past it.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the entry for `source` compiled with `options`.
+    pub fn entry_path(&self, source: &str, options: &CompilerOptions) -> PathBuf {
+        self.dir.join(format!("{:016x}.loxc", cache_key(source, options)))
+    }
+
+    pub fn load(&self, source: &str, options: &CompilerOptions) -> Option<Chunk> {
+        let path = self.entry_path(source, options);
+        let bytes = fs::read(&path).ok()?;
+        let code = bytes.strip_prefix(entry_header(source).as_slice())?;
+        let chunk = bytecode::decode(code).ok()?;
+
+        // Mark the entry as recently used so eviction keeps it
+        let _ = fs::File::options().append(true).open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(chunk)
+    }
+
+    pub fn store(&self, source: &str, options: &CompilerOptions, chunk: &Chunk) {
+        let path = self.entry_path(source, options);
+        // Write to a temporary file first so concurrent runs never see a
+        // partially written entry
+        let temp = path.with_extension(format!("tmp{}", std::process::id()));
+
+        let mut bytes = entry_header(source);
+        bytes.extend_from_slice(&bytecode::encode(chunk));
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temp, bytes))
+            .and_then(|_| fs::rename(&temp, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+            return;
+        }
+        self.evict();
+    }
+
+    /// Removes the least recently used entries past `max_entries`.
+    fn evict(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "loxc" {
+                    return None;
+                }
+                Some((fs::metadata(&path).ok()?.modified().ok()?, path))
+            })
+            .collect();
+        if entries.len() <= self.max_entries {
+            return;
+        }
+
+        entries.sort();
+        let excess = entries.len() - self.max_entries;
+        for (_, path) in &entries[..excess] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// What an entry for `source` starts with: the length of the source and
+/// the source itself.
+fn entry_header(source: &str) -> Vec<u8> {
+    let mut header = (source.len() as u64).to_le_bytes().to_vec();
+    header.extend_from_slice(source.as_bytes());
+    header
+}
+
+/// FNV-1a over everything that affects the compiled code. Unlike
+/// `DefaultHasher` it is stable across Rust releases.
+fn cache_key(source: &str, options: &CompilerOptions) -> u64 {
+    let parts: [&[u8]; 4] = [
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        &[bytecode::FORMAT_VERSION],
+        &[options.extensions as u8],
+        source.as_bytes(),
+    ];
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
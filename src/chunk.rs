@@ -1,3 +1,8 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+use crate::object::Function;
 use crate::value::Value;
 
 #[derive(Debug)]
@@ -6,24 +11,209 @@ pub enum OpCode {
     Nil,
     True,
     False,
+    Pop,
+    /// Operand is the constant holding the global's name.
+    DefineGlobal(u8),
+    GetGlobal(u8),
+    SetGlobal(u8),
+    /// Runs the module at the path in the operand's constant, unless it
+    /// already ran, and pushes its namespace.
+    Import(u8),
+    /// Operand is the stack slot of the local.
+    GetLocal(u8),
+    SetLocal(u8),
     Equal,
     Greater,
+    GreaterEqual,
     Less,
+    LessEqual,
     Add,
     Substract,
     Multiply,
     Divide,
+    Modulo,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    BitNot,
     Not,
     Negate,
+    Print,
+    /// Jumps forward by the operand, counted from the next instruction.
+    Jump(u16),
+    /// Like `Jump`, taken when the value on top of the stack is falsey. The
+    /// value is left on the stack.
+    JumpIfFalse(u16),
+    /// Jumps backward by the operand, counted from the next instruction.
+    Loop(u16),
+    /// Advances the iteration over the value below the iteration state on
+    /// top of the stack. If there is another element, the state is updated
+    /// and the element pushed; otherwise `nil` is pushed and it jumps forward
+    /// by the operand, counted from the next instruction. A `nil` state
+    /// starts the iteration.
+    ForIter(u16),
+    /// Starts a `try` block whose handler begins the operand's distance
+    /// forward, counted from the next instruction. A value thrown before
+    /// the matching `PopHandler` unwinds the stack back to its height here
+    /// and is pushed for the handler.
+    PushHandler(u16),
+    /// Ends the innermost `try` block.
+    PopHandler,
+    /// Throws the value on top of the stack.
+    Throw,
+    /// Calls the value below the operand's count of arguments, replacing
+    /// them all with the result.
+    Call(u8),
+    /// Replaces the operand's count of values on top of the stack with a
+    /// list holding them, the first one pushed coming first.
+    BuildList(u8),
+    /// Like `BuildList`, for a map with the operand's count of entries,
+    /// each pushed as its key followed by its value.
+    BuildMap(u8),
+    /// Replaces a container and an index on top of the stack with the
+    /// element at the index.
+    GetIndex,
+    /// Sets the element at an index of a container to a value, replacing
+    /// all three on top of the stack with the value.
+    SetIndex,
+    /// Replaces a list on top of the stack with its elements, the first one
+    /// lowest. The list must have exactly the operand's count of elements.
+    Unpack(u8),
+    /// Replaces a map and the operand's count of keys on top of the stack
+    /// with the value for each key, in the same order.
+    UnpackMap(u8),
+    /// Replaces a container and the start and end bounds on top of the
+    /// stack with the slice between them. A `nil` bound was left out.
+    Slice,
+    /// Replaces the start and end on top of the stack with the range
+    /// between them.
+    Range,
+    /// Returns the value on top of the stack from the current function.
     Return,
 }
 
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Constant(_) => "Constant",
+            Self::Nil => "Nil",
+            Self::True => "True",
+            Self::False => "False",
+            Self::Pop => "Pop",
+            Self::DefineGlobal(_) => "DefineGlobal",
+            Self::GetGlobal(_) => "GetGlobal",
+            Self::SetGlobal(_) => "SetGlobal",
+            Self::Import(_) => "Import",
+            Self::GetLocal(_) => "GetLocal",
+            Self::SetLocal(_) => "SetLocal",
+            Self::Equal => "Equal",
+            Self::Greater => "Greater",
+            Self::GreaterEqual => "GreaterEqual",
+            Self::Less => "Less",
+            Self::LessEqual => "LessEqual",
+            Self::Add => "Add",
+            Self::Substract => "Substract",
+            Self::Multiply => "Multiply",
+            Self::Divide => "Divide",
+            Self::Modulo => "Modulo",
+            Self::BitAnd => "BitAnd",
+            Self::BitOr => "BitOr",
+            Self::BitXor => "BitXor",
+            Self::ShiftLeft => "ShiftLeft",
+            Self::ShiftRight => "ShiftRight",
+            Self::BitNot => "BitNot",
+            Self::Not => "Not",
+            Self::Negate => "Negate",
+            Self::Print => "Print",
+            Self::Jump(_) => "Jump",
+            Self::JumpIfFalse(_) => "JumpIfFalse",
+            Self::Loop(_) => "Loop",
+            Self::ForIter(_) => "ForIter",
+            Self::PushHandler(_) => "PushHandler",
+            Self::PopHandler => "PopHandler",
+            Self::Throw => "Throw",
+            Self::Call(_) => "Call",
+            Self::BuildList(_) => "BuildList",
+            Self::BuildMap(_) => "BuildMap",
+            Self::GetIndex => "GetIndex",
+            Self::SetIndex => "SetIndex",
+            Self::Slice => "Slice",
+            Self::Unpack(_) => "Unpack",
+            Self::UnpackMap(_) => "UnpackMap",
+            Self::Range => "Range",
+            Self::Return => "Return",
+        }
+    }
+
+    /// Net change in stack height caused by executing the instruction.
+    pub fn stack_effect(&self) -> isize {
+        match self {
+            Self::Constant(_) | Self::Nil | Self::True | Self::False |
+            Self::GetGlobal(_) | Self::GetLocal(_) | Self::Import(_) |
+            Self::ForIter(_) => 1,
+            Self::Not | Self::Negate | Self::BitNot |
+            Self::SetGlobal(_) | Self::SetLocal(_) => 0,
+            Self::Jump(_) | Self::JumpIfFalse(_) | Self::Loop(_) => 0,
+            Self::PushHandler(_) | Self::PopHandler => 0,
+            Self::Pop | Self::DefineGlobal(_) | Self::Print | Self::Throw => -1,
+            Self::Equal | Self::Greater | Self::GreaterEqual |
+            Self::Less | Self::LessEqual |
+            Self::Add | Self::Substract | Self::Multiply | Self::Divide | Self::Modulo |
+            Self::BitAnd | Self::BitOr | Self::BitXor |
+            Self::ShiftLeft | Self::ShiftRight | Self::Range => -1,
+            Self::Call(arg_count) => -(*arg_count as isize),
+            Self::BuildList(count) => 1 - *count as isize,
+            Self::BuildMap(count) => 1 - 2 * *count as isize,
+            Self::GetIndex => -1,
+            Self::SetIndex | Self::Slice => -2,
+            Self::Unpack(count) => *count as isize - 1,
+            Self::UnpackMap(_) => -1,
+            Self::Return => -1,
+        }
+    }
+
+    /// Offset a jump instruction at `offset` transfers control to, or `None`
+    /// if this isn't a jump or it would jump before the start of the chunk.
+    pub fn jump_target(&self, offset: usize) -> Option<usize> {
+        match self {
+            Self::Jump(distance) | Self::JumpIfFalse(distance) |
+            Self::ForIter(distance) | Self::PushHandler(distance) => {
+                Some(offset + 1 + *distance as usize)
+            },
+            Self::Loop(distance) => (offset + 1).checked_sub(*distance as usize),
+            _ => None,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<usize> {
+        match self {
+            Self::Constant(id) | Self::DefineGlobal(id) |
+            Self::GetGlobal(id) | Self::SetGlobal(id) | Self::Import(id) |
+            Self::GetLocal(id) | Self::SetLocal(id) |
+            Self::Call(id) | Self::BuildList(id) | Self::BuildMap(id) |
+            Self::Unpack(id) | Self::UnpackMap(id) => vec![*id as usize],
+            Self::Jump(distance) | Self::JumpIfFalse(distance) |
+            Self::Loop(distance) | Self::ForIter(distance) |
+            Self::PushHandler(distance) => vec![*distance as usize],
+            _ => Vec::new(),
+        }
+    }
+}
+
 pub struct Chunk {
     code: Vec<OpCode>,
     lines: Vec<usize>,
     constants: Vec<Value>,
 }
 
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Chunk {
     pub fn new() -> Chunk {
         Chunk {
@@ -45,13 +235,208 @@ impl Chunk {
         &self.lines
     }
 
+    /// Source line of the instruction at `offset`.
+    pub fn line_at(&self, offset: usize) -> usize {
+        self.lines[offset]
+    }
+
+    /// Number of instructions in the chunk.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Size in bytes of the encoded instructions. Every opcode has the same
+    /// width, so this is the instruction count times the size of `OpCode`.
+    pub fn byte_size(&self) -> usize {
+        self.code.len() * mem::size_of::<OpCode>()
+    }
+
+    /// Number of constants of each value type, keyed by type name.
+    pub fn constant_counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for constant in &self.constants {
+            *counts.entry(constant.type_name()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Iterates over the instructions as `(offset, opcode, line)` tuples.
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, &OpCode, usize)> {
+        self.code.iter()
+            .zip(&self.lines)
+            .enumerate()
+            .map(|(offset, (op, line))| (offset, op, *line))
+    }
+
     pub fn write(&mut self, op: OpCode, line: usize) {
         self.code.push(op);
         self.lines.push(line);
     }
 
-    pub fn add_constant(&mut self, value: Value) -> u8 {
+    /// Points the forward jump at `offset` to the next instruction that will
+    /// be written.
+    pub(crate) fn patch_jump(&mut self, offset: usize, distance: u16) {
+        match &mut self.code[offset] {
+            OpCode::Jump(target) | OpCode::JumpIfFalse(target) |
+            OpCode::ForIter(target) | OpCode::PushHandler(target) => *target = distance,
+            op => panic!("Patching {:?}, which is not a forward jump", op),
+        }
+    }
+
+    /// Adds a constant, returning its index. Callers must check that the
+    /// index fits the operand of the instruction that loads it.
+    pub fn add_constant(&mut self, value: Value) -> usize {
         self.constants.push(value);
-        (self.constants.len() - 1).try_into().unwrap()
+        self.constants.len() - 1
+    }
+}
+
+/// How deeply functions may be nested in the constant tables of a chunk
+/// before verifying or decoding it gives up, rather than recursing until the
+/// stack overflows.
+pub const MAX_FUNCTION_DEPTH: usize = 256;
+
+/// A chunk that could crash or confuse the VM if it were run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid instruction at {:04}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl Chunk {
+    /// Checks that every constant operand is in range, that global names are
+    /// strings, that jumps land inside the chunk, that every instruction is
+    /// reached with the same stack height on all paths and that height
+    /// covers the values it pops and the locals it accesses. Functions in the
+    /// constant table are checked as well, down to [`MAX_FUNCTION_DEPTH`].
+    /// Chunks from the compiler always pass; this is for chunks loaded from
+    /// elsewhere.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        // Slot 0 holds the script itself
+        self.verify_frame(1, 0)
+    }
+
+    fn verify_function(function: &Function, depth: usize) -> Result<(), VerifyError> {
+        if depth > MAX_FUNCTION_DEPTH {
+            return Err(VerifyError {
+                offset: 0,
+                message: format!("Functions nested more than {} deep", MAX_FUNCTION_DEPTH),
+            });
+        }
+        // The callee and its arguments
+        let slots = function.arity as isize + function.variadic as isize + 1;
+        function.chunk.verify_frame(slots, depth).map_err(|err| VerifyError {
+            offset: err.offset,
+            message: format!("In {}: {}", function, err.message),
+        })
     }
-}
\ No newline at end of file
+
+    /// Verifies the code of a function whose frame starts with `slots`
+    /// values on the stack, nested `depth` functions deep.
+    fn verify_frame(&self, slots: isize, depth: usize) -> Result<(), VerifyError> {
+        let error = |offset, message: String| Err(VerifyError { offset, message });
+
+        for constant in &self.constants {
+            if let Value::Function(function) = constant {
+                Self::verify_function(function, depth + 1)?;
+            }
+        }
+
+        // Stack height on entry to each instruction, found by following
+        // every path through the code
+        let mut heights: Vec<Option<isize>> = vec![None; self.code.len()];
+        let mut pending = vec![(0, slots)];
+        if self.code.is_empty() {
+            pending.clear();
+        }
+
+        while let Some((offset, height)) = pending.pop() {
+            match heights[offset] {
+                Some(seen) if seen == height => continue,
+                Some(seen) => return error(offset, format!(
+                    "Reached with stack height {} and {}", seen, height
+                )),
+                None => heights[offset] = Some(height),
+            }
+
+            let op = &self.code[offset];
+            match op {
+                OpCode::Constant(id) if *id as usize >= self.constants.len() => {
+                    return error(offset, format!("Constant {} out of range", id));
+                },
+                OpCode::DefineGlobal(id) | OpCode::GetGlobal(id) | OpCode::SetGlobal(id)
+                    if !matches!(self.constants.get(*id as usize), Some(Value::String(_))) =>
+                {
+                    return error(offset, format!("Constant {} is not a global name", id));
+                },
+                OpCode::Import(id)
+                    if !matches!(self.constants.get(*id as usize), Some(Value::String(_))) =>
+                {
+                    return error(offset, format!("Constant {} is not a module path", id));
+                },
+                OpCode::GetLocal(slot) | OpCode::SetLocal(slot) if *slot as isize >= height => {
+                    return error(offset, format!("Local slot {} out of range", slot));
+                },
+                _ => {},
+            }
+
+            let pops = match op {
+                OpCode::Constant(_) | OpCode::Nil | OpCode::True | OpCode::False |
+                OpCode::GetGlobal(_) | OpCode::GetLocal(_) | OpCode::Import(_) |
+                OpCode::Jump(_) | OpCode::Loop(_) |
+                OpCode::PushHandler(_) | OpCode::PopHandler => 0,
+                OpCode::Not | OpCode::Negate | OpCode::BitNot | OpCode::Pop |
+                OpCode::DefineGlobal(_) | OpCode::SetGlobal(_) | OpCode::SetLocal(_) |
+                OpCode::JumpIfFalse(_) | OpCode::Print | OpCode::Return |
+                OpCode::Unpack(_) | OpCode::Throw => 1,
+                OpCode::ForIter(_) => 2,
+                OpCode::Call(arg_count) => *arg_count as isize + 1,
+                OpCode::BuildList(count) => *count as isize,
+                OpCode::BuildMap(count) => 2 * *count as isize,
+                OpCode::UnpackMap(count) => *count as isize + 1,
+                OpCode::SetIndex | OpCode::Slice => 3,
+                _ => 2,
+            };
+            if height < pops {
+                return error(offset, format!("{} underflows the stack", op.name()));
+            }
+            let height = height + op.stack_effect();
+
+            let jumps = matches!(
+                op, OpCode::Jump(_) | OpCode::JumpIfFalse(_) | OpCode::Loop(_) |
+                OpCode::ForIter(_) | OpCode::PushHandler(_)
+            );
+            if jumps {
+                // Handlers start with the thrown value on the stack
+                let target_height = height + matches!(op, OpCode::PushHandler(_)) as isize;
+                match op.jump_target(offset) {
+                    Some(target) if target < self.code.len() => pending.push((target, target_height)),
+                    _ => return error(offset, "Jump target out of range".to_owned()),
+                }
+            }
+
+            let falls_through = !matches!(
+                op, OpCode::Return | OpCode::Throw | OpCode::Jump(_) | OpCode::Loop(_)
+            );
+            if falls_through {
+                if offset + 1 == self.code.len() {
+                    return error(offset, "Code does not end with Return".to_owned());
+                }
+                pending.push((offset + 1, height));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
+use std::rc::Rc;
+
 use crate::chunk::{Chunk, OpCode};
 use crate::debug;
+use crate::object::Function;
 use crate::scanner::{Scanner, Token, TokenType};
 use crate::value::Value;
 
@@ -7,7 +10,7 @@ struct Parser<'s> {
     scanner: Scanner<'s>,
     previous: Option<Token<'s>>,
     current: Option<Token<'s>>,
-    had_error: bool,
+    diagnostics: Vec<String>,
     panic_mode: bool,
 }
 
@@ -17,18 +20,18 @@ enum ErrorSource {
 }
 
 impl<'s> Parser<'s> {
-    fn new(source: &'s str) -> Self {
-        let scanner = Scanner::new(source);
+    fn new(source: &'s str, extensions: bool) -> Self {
+        let scanner = Scanner::with_extensions(source, extensions);
         Self {
             scanner,
             previous: None,
             current: None,
-            had_error: false,
+            diagnostics: Vec::new(),
             panic_mode: false,
         }
     }
 
-    pub fn advance(&mut self) {
+    fn advance(&mut self) {
         self.previous = self.current.take();
 
         loop {
@@ -45,8 +48,8 @@ impl<'s> Parser<'s> {
         }
     }
 
-    pub fn consume(&mut self, token_type: TokenType, message: &'static str) {
-        if self.current.as_ref().is_some_and(|t| t.token_type == token_type) {
+    fn consume(&mut self, token_type: TokenType, message: &'static str) {
+        if self.check(token_type) {
             self.advance();
         }
         else {
@@ -54,11 +57,29 @@ impl<'s> Parser<'s> {
         }
     }
 
-    pub fn error(&mut self, message: &'static str) {
+    fn check(&self, token_type: TokenType) -> bool {
+        self.current.as_ref().is_some_and(|t| t.token_type == token_type)
+    }
+
+    /// Whether the token after the current one has the given type.
+    fn check_next(&self, token_type: TokenType) -> bool {
+        let mut scanner = self.scanner.clone();
+        matches!(scanner.scan_token(), Ok(Some(tok)) if tok.token_type == token_type)
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    fn error(&mut self, message: &'static str) {
         self.error_at(ErrorSource::Previous, message);
     }
 
-    pub fn error_at_current(&mut self, message: &'static str) {
+    fn error_at_current(&mut self, message: &'static str) {
         self.error_at(ErrorSource::Current, message);
     }
 
@@ -73,16 +94,20 @@ impl<'s> Parser<'s> {
             ErrorSource::Previous => self.previous.as_ref(),
         };
 
-        match token {
+        let diagnostic = match token {
             Some(token) => {
-                eprintln!("[line {}] Error at '{}': {}", token.line, token.span, message)
+                format!("[line {}] Error at '{}': {}", token.line, token.span, message)
             },
             None => {
-                eprintln!("[line {}] Error at end: {}", self.scanner.line, message)
+                format!("[line {}] Error at end: {}", self.scanner.line, message)
             }
-        }
+        };
 
-        self.had_error = true;
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn had_error(&self) -> bool {
+        !self.diagnostics.is_empty()
     }
 }
 
@@ -94,6 +119,11 @@ enum Precedence {
     And,
     Equality,
     Comparison,
+    Range,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
     Term,
     Factor,
     Unary,
@@ -109,7 +139,12 @@ impl Precedence {
             Self::Or => Self::And,
             Self::And => Self::Equality,
             Self::Equality => Self::Comparison,
-            Self::Comparison => Self::Term,
+            Self::Comparison => Self::Range,
+            Self::Range => Self::BitOr,
+            Self::BitOr => Self::BitXor,
+            Self::BitXor => Self::BitAnd,
+            Self::BitAnd => Self::Shift,
+            Self::Shift => Self::Term,
             Self::Term => Self::Factor,
             Self::Factor => Self::Unary,
             Self::Unary => Self::Call,
@@ -119,7 +154,8 @@ impl Precedence {
     }
 }
 
-type ParseFn<'s> = fn(&mut Compiler<'s>) -> ();
+/// Parse functions get whether the expression may be an assignment target.
+type ParseFn<'s> = fn(&mut Compiler<'s>, bool) -> ();
 
 struct ParseRule<'s> {
     prefix: Option<ParseFn<'s>>,
@@ -158,55 +194,169 @@ macro_rules! parse_rule {
     };
 }
 
-impl<'s> Into<ParseRule<'s>> for TokenType {
-    fn into(self) -> ParseRule<'s> {
-        match self {
-            Self::LeftParen => parse_rule!(grouping, None, None),
-            Self::Minus => parse_rule!(unary, binary, Term),
-            Self::Plus => parse_rule!(None, binary, Term),
-            Self::Slash => parse_rule!(None, binary, Factor),
-            Self::Star => parse_rule!(None, binary, Factor),
-            Self::Number => parse_rule!(number, None, None),
-            Self::False | Self::True | Self::Nil => parse_rule!(literal, None, None),
-            Self::Bang => parse_rule!(unary, None, None),
-            Self::BangEqual | Self::EqualEqual => parse_rule!(None, binary, Equality),
-            Self::Greater | Self::GreaterEqual |
-            Self::Less | Self::LessEqual => parse_rule!(None, binary, Comparison),
+impl<'s> From<TokenType> for ParseRule<'s> {
+    fn from(token_type: TokenType) -> Self {
+        match token_type {
+            TokenType::LeftParen => parse_rule!(grouping, call, Call),
+            TokenType::LeftBracket => parse_rule!(list, index, Call),
+            // Braces starting a statement are a block instead
+            TokenType::LeftBrace => parse_rule!(map, None, None),
+            TokenType::Minus => parse_rule!(unary, binary, Term),
+            TokenType::Plus => parse_rule!(None, binary, Term),
+            TokenType::Slash => parse_rule!(None, binary, Factor),
+            TokenType::Star => parse_rule!(None, binary, Factor),
+            TokenType::Percent => parse_rule!(None, binary, Factor),
+            TokenType::Number => parse_rule!(number, None, None),
+            TokenType::String | TokenType::RawString => parse_rule!(string, None, None),
+            TokenType::Identifier => parse_rule!(variable, None, None),
+            TokenType::False | TokenType::True | TokenType::Nil => parse_rule!(literal, None, None),
+            // A statement starting with `fun` is a declaration instead
+            TokenType::Fun => parse_rule!(lambda, None, None),
+            TokenType::Bang | TokenType::Tilde => parse_rule!(unary, None, None),
+            TokenType::DotDot => parse_rule!(None, binary, Range),
+            TokenType::Pipe => parse_rule!(None, binary, BitOr),
+            TokenType::Caret => parse_rule!(None, binary, BitXor),
+            TokenType::Ampersand => parse_rule!(None, binary, BitAnd),
+            TokenType::LessLess | TokenType::GreaterGreater => parse_rule!(None, binary, Shift),
+            TokenType::BangEqual | TokenType::EqualEqual => parse_rule!(None, binary, Equality),
+            TokenType::Greater | TokenType::GreaterEqual |
+            TokenType::Less | TokenType::LessEqual => parse_rule!(None, binary, Comparison),
+            TokenType::And => parse_rule!(None, and, And),
+            TokenType::Or => parse_rule!(None, or, Or),
             _ => parse_rule!(None, None, None),
         }
     }
 }
 
+/// Compilation failed, with one diagnostic per reported error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub diagnostics: Vec<String>,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.diagnostics.join("\n"))
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Clone)]
+pub struct CompilerOptions {
+    /// Disassemble the compiled chunk to stderr. Defaults to on with the
+    /// `debug_print_code` feature.
+    pub print_code: bool,
+    /// Accept syntax and semantics beyond the reference Lox language.
+    /// Turning this off (`--std=lox`) compiles exactly the language from
+    /// the book. Defaults to on with the `extensions` feature.
+    pub extensions: bool,
+}
+
+// Only derivable when every feature is off
+#[allow(clippy::derivable_impls)]
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self {
+            print_code: cfg!(feature = "debug_print_code"),
+            extensions: cfg!(feature = "extensions"),
+        }
+    }
+}
+
+struct Local<'s> {
+    name: &'s str,
+    /// Scope depth, or `None` while the initializer is being compiled.
+    depth: Option<usize>,
+}
+
+/// A loop being compiled, which `break` and `continue` can leave.
+struct Loop<'s> {
+    label: Option<&'s str>,
+    /// Count of locals outside the loop body, and of `try` blocks around
+    /// the loop, which leaving the body drops down to.
+    locals: usize,
+    handlers: usize,
+    /// Where `continue` goes, or `None` if it is after the body and the
+    /// jumps to it are patched in `continues`.
+    continue_target: Option<usize>,
+    continues: Vec<usize>,
+    breaks: Vec<usize>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum FunctionType {
+    Function,
+    Script,
+}
+
+/// The function being compiled, along with its scopes.
+struct FunctionState<'s> {
+    function: Function,
+    kind: FunctionType,
+    locals: Vec<Local<'s>>,
+    scope_depth: usize,
+    /// Innermost loop last.
+    loops: Vec<Loop<'s>>,
+    /// Count of `try` blocks being compiled.
+    handlers: usize,
+}
+
+impl<'s> FunctionState<'s> {
+    fn new(kind: FunctionType, name: Option<&str>) -> Self {
+        Self {
+            function: Function::new(name.map(Rc::from)),
+            kind,
+            // Slot 0 holds the function being called, and can't be named
+            locals: vec![Local { name: "", depth: Some(0) }],
+            scope_depth: 0,
+            loops: Vec::new(),
+            handlers: 0,
+        }
+    }
+}
+
 pub struct Compiler<'s> {
+    source: &'s str,
+    options: CompilerOptions,
     parser: Parser<'s>,
-    compiling_chunk: Option<Chunk>,
+    /// Innermost function last.
+    functions: Vec<FunctionState<'s>>,
     // Note for later chapters:
-    // Hold a single scanner and a stack of (Class)Compiler contexts
+    // Hold a stack of ClassCompiler contexts as well
 }
 
 impl<'s> Compiler<'s> {
     pub fn new(source: &'s str) -> Self {
-        let mut parser = Parser::new(source);
-        parser.advance();
-        Self { parser, compiling_chunk: None }
+        Self::with_options(source, CompilerOptions::default())
     }
 
-    pub fn compile(&mut self) -> Result<Chunk, ()> {
-        self.compiling_chunk = Some(Chunk::new());
+    pub fn with_options(source: &'s str, options: CompilerOptions) -> Self {
+        let mut parser = Parser::new(source, options.extensions);
+        parser.advance();
+        Self {
+            source,
+            options,
+            parser,
+            functions: Vec::new(),
+        }
+    }
 
-        self.expression();
+    pub fn compile(&mut self) -> Result<Chunk, CompileError> {
+        self.functions.push(FunctionState::new(FunctionType::Script, None));
 
-        if self.parser.current.is_some() {
-            self.parser.error_at_current("Expected end of expression");
+        while self.parser.current.is_some() {
+            self.declaration();
         }
 
-        self.end_compiler();
+        let script = self.end_compiler();
 
-        if self.parser.had_error {
-            Err(())
+        if self.parser.had_error() {
+            let diagnostics = std::mem::take(&mut self.parser.diagnostics);
+            Err(CompileError { diagnostics })
         }
         else {
-            self.compiling_chunk.take().ok_or(())
+            Ok(script.chunk)
         }
     }
 
@@ -216,10 +366,16 @@ impl<'s> Compiler<'s> {
             Some(tok) => tok.line,
             None => 0,
         };
+        self.emit_at(op, line);
+    }
+
+    fn emit_at(&mut self, op: OpCode, line: usize) {
         self.current_chunk().write(op, line);
     }
 
+    /// Functions without an explicit return value return nil.
     fn emit_return(&mut self) {
+        self.emit(OpCode::Nil);
         self.emit(OpCode::Return);
     }
 
@@ -228,24 +384,82 @@ impl<'s> Compiler<'s> {
         self.emit(OpCode::Constant(constant));
     }
 
-    fn make_constant(&mut self, value: Value) -> u8 {
-        self.current_chunk().add_constant(value)
+    /// Emits a forward jump with a placeholder distance, returning its offset
+    /// for `patch_jump`.
+    fn emit_jump(&mut self, op: fn(u16) -> OpCode) -> usize {
+        self.emit(op(u16::MAX));
+        self.current_chunk().len() - 1
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let distance = self.current_chunk().len() - offset - 1;
+
+        match u16::try_from(distance) {
+            Ok(distance) => self.current_chunk().patch_jump(offset, distance),
+            Err(_) => self.parser.error("Too much code to jump over"),
+        }
+    }
+
+    /// Emits a jump back to `loop_start`, attributed to `line` so it shows
+    /// up under the loop's header rather than the end of its body.
+    fn emit_loop(&mut self, loop_start: usize, line: usize) {
+        // Counted from the instruction after the loop
+        let distance = self.current_chunk().len() + 1 - loop_start;
+
+        match u16::try_from(distance) {
+            Ok(distance) => self.emit_at(OpCode::Loop(distance), line),
+            Err(_) => self.parser.error("Loop body too large"),
+        }
     }
 
-    fn end_compiler(&mut self) {
-        if cfg!(feature = "debug_print_code") {
-            if !self.parser.had_error {
-                debug::disassemble_chunk(self.current_chunk(), "code");
+    /// Reports an error for syntax that only exists with `extensions` on.
+    fn require_extension(&mut self, message: &'static str) {
+        if !self.options.extensions {
+            self.parser.error(message);
+        }
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let constant = self.current_chunk().add_constant(value);
+
+        match u8::try_from(constant) {
+            Ok(constant) => constant,
+            Err(_) => {
+                self.parser.error("Too many constants in one chunk");
+                0
             }
         }
+    }
+
+    /// Finishes the innermost function and returns it.
+    fn end_compiler(&mut self) -> Function {
         self.emit_return();
+        let state = self.functions.pop().unwrap();
+
+        // The script's disassembly includes every function within it
+        if state.kind == FunctionType::Script
+            && self.options.print_code && !self.parser.had_error()
+        {
+            debug::disassemble_chunk_with_source(&state.function.chunk, "code", self.source);
+        }
+        state.function
     }
 
-    fn binary(&mut self) {
+    fn binary(&mut self, _can_assign: bool) {
         let operator_type = self.parser.previous
             .as_ref().unwrap().token_type;
 
-        let rule: ParseRule = operator_type.clone().into();
+        match operator_type {
+            TokenType::Percent => self.require_extension("'%' is not part of standard Lox"),
+            TokenType::Ampersand | TokenType::Pipe | TokenType::Caret |
+            TokenType::LessLess | TokenType::GreaterGreater => {
+                self.require_extension("Bitwise operators are not part of standard Lox");
+            },
+            TokenType::DotDot => self.require_extension("Ranges are not part of standard Lox"),
+            _ => {},
+        }
+
+        let rule: ParseRule = operator_type.into();
 
         self.parse_precedence(Precedence::below(&rule.precedence));
 
@@ -254,26 +468,107 @@ impl<'s> Compiler<'s> {
             TokenType::Minus => self.emit(OpCode::Substract),
             TokenType::Star => self.emit(OpCode::Multiply),
             TokenType::Slash => self.emit(OpCode::Divide),
+            TokenType::Percent => self.emit(OpCode::Modulo),
+            TokenType::DotDot => self.emit(OpCode::Range),
+            TokenType::Ampersand => self.emit(OpCode::BitAnd),
+            TokenType::Pipe => self.emit(OpCode::BitOr),
+            TokenType::Caret => self.emit(OpCode::BitXor),
+            TokenType::LessLess => self.emit(OpCode::ShiftLeft),
+            TokenType::GreaterGreater => self.emit(OpCode::ShiftRight),
             TokenType::BangEqual => {
                 self.emit(OpCode::Equal);
                 self.emit(OpCode::Not);
             },
             TokenType::EqualEqual => self.emit(OpCode::Equal),
             TokenType::Greater => self.emit(OpCode::Greater),
-            TokenType::GreaterEqual => {
-                self.emit(OpCode::Less);
-                self.emit(OpCode::Not);
-            },
+            TokenType::GreaterEqual => self.emit(OpCode::GreaterEqual),
             TokenType::Less => self.emit(OpCode::Less),
-            TokenType::LessEqual => {
-                self.emit(OpCode::Greater);
-                self.emit(OpCode::Not);
-            },
+            TokenType::LessEqual => self.emit(OpCode::LessEqual),
             _ => unreachable!(),
         }
     }
 
-    fn literal(&mut self) {
+    /// The left operand is on the stack. If it is falsey it is the result,
+    /// otherwise it is replaced by the right operand.
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+        self.emit(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+
+        self.patch_jump(end_jump);
+    }
+
+    /// Like `and`, but the left operand is the result when it is truthy.
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit(OpCode::Pop);
+        self.parse_precedence(Precedence::Or);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let arg_count = self.argument_list();
+        self.emit(OpCode::Call(arg_count));
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.require_extension("Indexing is not part of standard Lox");
+
+        // A left out slice bound compiles to nil
+        if self.parser.check(TokenType::Colon) {
+            self.emit(OpCode::Nil);
+        }
+        else {
+            self.expression();
+        }
+
+        if self.parser.match_token(TokenType::Colon) {
+            if self.parser.check(TokenType::RightBracket) {
+                self.emit(OpCode::Nil);
+            }
+            else {
+                self.expression();
+            }
+            self.parser.consume(TokenType::RightBracket, "Expected ']' after slice");
+            self.emit(OpCode::Slice);
+            return;
+        }
+        self.parser.consume(TokenType::RightBracket, "Expected ']' after index");
+
+        if can_assign && self.parser.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit(OpCode::SetIndex);
+        }
+        else {
+            self.emit(OpCode::GetIndex);
+        }
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.parser.check(TokenType::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == u8::MAX as usize {
+                    self.parser.error("Can't have more than 255 arguments");
+                }
+                arg_count += 1;
+
+                if !self.parser.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.parser.consume(TokenType::RightParen, "Expected ')' after arguments");
+        arg_count.min(u8::MAX as usize) as u8
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
         match self.parser.previous.as_ref().unwrap().token_type {
             TokenType::False => self.emit(OpCode::False),
             TokenType::True => self.emit(OpCode::True),
@@ -282,25 +577,143 @@ impl<'s> Compiler<'s> {
         }
     }
 
-    fn grouping(&mut self) {
+    fn grouping(&mut self, _can_assign: bool) {
         self.expression();
         self.parser.consume(TokenType::RightParen, "Expected ')' after expression");
     }
 
-    fn number(&mut self) {
-        let value: f64 = self.parser.previous.as_ref().unwrap().span.parse().unwrap();
-        self.emit_constant(Value::Number(value));
+    fn list(&mut self, _can_assign: bool) {
+        self.require_extension("Lists are not part of standard Lox");
+
+        let mut count: usize = 0;
+        while !self.parser.check(TokenType::RightBracket) {
+            self.expression();
+            if count == u8::MAX as usize {
+                self.parser.error("Can't have more than 255 elements in a list literal");
+            }
+            count += 1;
+
+            // Allows a trailing comma
+            if !self.parser.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+        self.parser.consume(TokenType::RightBracket, "Expected ']' after list elements");
+        self.emit(OpCode::BuildList(count.min(u8::MAX as usize) as u8));
+    }
+
+    fn map(&mut self, _can_assign: bool) {
+        self.require_extension("Maps are not part of standard Lox");
+
+        let mut count: usize = 0;
+        while !self.parser.check(TokenType::RightBrace) {
+            self.expression();
+            self.parser.consume(TokenType::Colon, "Expected ':' after map key");
+            self.expression();
+            if count == u8::MAX as usize {
+                self.parser.error("Can't have more than 255 entries in a map literal");
+            }
+            count += 1;
+
+            if !self.parser.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+        self.parser.consume(TokenType::RightBrace, "Expected '}' after map entries");
+        self.emit(OpCode::BuildMap(count.min(u8::MAX as usize) as u8));
+    }
+
+    fn lambda(&mut self, _can_assign: bool) {
+        self.require_extension("Anonymous functions are not part of standard Lox");
+        self.function(FunctionType::Function, "lambda");
     }
 
-    fn unary(&mut self) {
+    fn number(&mut self, _can_assign: bool) {
+        let span = self.parser.previous.as_ref().unwrap().span;
+
+        // Digit separators, only scanned with extensions on
+        let without_separators;
+        let span = if span.contains('_') {
+            without_separators = span.replace('_', "");
+            &without_separators
+        }
+        else {
+            span
+        };
+
+        // Only scanned with extensions on, and validated by the scanner
+        let radix = match span.get(..2) {
+            Some("0x") => 16,
+            Some("0b") => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            let digits = &span[2..];
+            let value = match i64::from_str_radix(digits, radix) {
+                Ok(value) => Value::Int(value),
+                Err(_) => Value::Number(digits.chars()
+                    .filter_map(|digit| digit.to_digit(radix))
+                    .fold(0.0, |acc, digit| acc * radix as f64 + digit as f64)),
+            };
+            self.emit_constant(value);
+            return;
+        }
+
+        // Integer literals too large for an i64 become floats, and standard
+        // Lox only has floats
+        let value = match span.parse::<i64>() {
+            Ok(value) if self.options.extensions => Value::Int(value),
+            _ => Value::Number(span.parse().unwrap()),
+        };
+        self.emit_constant(value);
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        let value = self.string_value();
+        self.emit_constant(Value::String(value));
+    }
+
+    /// The value of the string literal that is the previous token. Escapes
+    /// are processed in regular strings with extensions on, and never in
+    /// raw strings.
+    fn string_value(&mut self) -> Rc<str> {
+        let Some(token) = self.parser.previous.as_ref() else {
+            return "".into();
+        };
+        let (span, raw) = (token.span, token.token_type == TokenType::RawString);
+
+        if raw {
+            self.require_extension("Raw strings are not part of standard Lox");
+        }
+
+        // Strip the quotes, and the prefix of raw strings
+        let contents = &span[1 + raw as usize..span.len() - 1];
+        if raw || !self.options.extensions {
+            return contents.into();
+        }
+        match unescape(contents) {
+            Ok(value) => value.into(),
+            Err(message) => {
+                self.parser.error(message);
+                contents.into()
+            },
+        }
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
         let operator_type = self.parser.previous
             .as_ref().unwrap().token_type.to_owned();
 
+        if operator_type == TokenType::Tilde {
+            self.require_extension("Bitwise operators are not part of standard Lox");
+        }
+
         self.parse_precedence(Precedence::Unary);
 
         match operator_type {
             TokenType::Bang => self.emit(OpCode::Not),
             TokenType::Minus => self.emit(OpCode::Negate),
+            TokenType::Tilde => self.emit(OpCode::BitNot),
             _ => unreachable!(),
         }
     }
@@ -309,14 +722,21 @@ impl<'s> Compiler<'s> {
         self.parser.advance();
         let tt = match self.parser.previous.as_ref() {
             Some(tok) => tok.token_type,
-            None => return,
+            None => {
+                self.parser.error("Expected expression");
+                return;
+            },
         };
 
         let rule: ParseRule = tt.into();
 
+        // Only a low precedence expression can be followed by `=`, so
+        // `a + b = c` isn't treated as assigning to `b`
+        let can_assign = precedence <= Precedence::Assignment;
+
         match rule.prefix {
             Some(ref func) => {
-                func(self);
+                func(self, can_assign);
 
 
                 loop {
@@ -340,19 +760,808 @@ impl<'s> Compiler<'s> {
                     let rule: ParseRule = tt.into();
                     
                     if let Some(ref func) = rule.infix {
-                        func(self);
+                        func(self, can_assign);
                     }
                 }
+
+                if can_assign && self.parser.match_token(TokenType::Equal) {
+                    self.parser.error("Invalid assignment target");
+                }
             },
             None => self.parser.error("Expected expression"),
         }
     }
 
+    fn identifier_constant(&mut self, name: &str) -> u8 {
+        self.make_constant(Value::String(name.into()))
+    }
+
+    /// Parses a variable name, declaring it if it's a local. Returns the
+    /// constant holding the name of a global, unused for locals.
+    fn parse_variable(&mut self, message: &'static str) -> u8 {
+        self.parser.consume(TokenType::Identifier, message);
+        let name = self.parser.previous.as_ref().map_or("", |tok| tok.span);
+
+        if self.state().scope_depth > 0 {
+            self.declare_variable(name);
+            return 0;
+        }
+        self.identifier_constant(name)
+    }
+
+    fn declare_variable(&mut self, name: &'s str) {
+        let state = self.state();
+        let redeclared = state.locals.iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= state.scope_depth))
+            .any(|local| local.name == name);
+        if redeclared {
+            self.parser.error("Already a variable with this name in this scope");
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: &'s str) {
+        if self.state().locals.len() > u8::MAX as usize {
+            self.parser.error("Too many local variables in scope");
+            return;
+        }
+        self.state().locals.push(Local { name, depth: None });
+    }
+
+    /// Makes the latest local usable, once its initializer is compiled.
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(state.scope_depth);
+        }
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.state().scope_depth > 0 {
+            // The local is already in place on the stack
+            self.mark_initialized();
+            return;
+        }
+        self.emit(OpCode::DefineGlobal(global));
+    }
+
+    fn resolve_local(&mut self, name: &str) -> Option<u8> {
+        let (slot, local) = self.state().locals.iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name)?;
+
+        if local.depth.is_none() {
+            self.parser.error("Can't read local variable in its own initializer");
+        }
+        Some(slot as u8)
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        let name = self.parser.previous.as_ref().unwrap().span;
+        self.named_variable(name, can_assign);
+    }
+
+    fn named_variable(&mut self, name: &str, can_assign: bool) {
+        type MakeOp = fn(u8) -> OpCode;
+        let (get_op, set_op, arg): (MakeOp, MakeOp, u8) = match self.resolve_local(name) {
+            Some(slot) => (OpCode::GetLocal, OpCode::SetLocal, slot),
+            None => {
+                let arg = self.identifier_constant(name);
+                (OpCode::GetGlobal, OpCode::SetGlobal, arg)
+            },
+        };
+
+        if can_assign && self.parser.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit(set_op(arg));
+        }
+        else {
+            self.emit(get_op(arg));
+        }
+    }
+
     fn expression(&mut self) {
         self.parse_precedence(Precedence::Assignment);
     }
 
+    fn declaration(&mut self) {
+        if self.parser.match_token(TokenType::Fun) {
+            self.fun_declaration();
+        }
+        else if self.parser.match_token(TokenType::Var) {
+            self.var_declaration();
+        }
+        else if self.parser.match_token(TokenType::Import) {
+            self.import_declaration();
+        }
+        else {
+            self.statement();
+        }
+
+        if self.parser.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expected function name");
+        // A local function may refer to itself in its body
+        self.mark_initialized();
+        let name = self.parser.previous.as_ref().map_or("", |tok| tok.span);
+        self.function(FunctionType::Function, name);
+        self.define_variable(global);
+    }
+
+    /// Compiles the parameters and body of a function, leaving it on the
+    /// stack.
+    fn function(&mut self, kind: FunctionType, name: &str) {
+        self.functions.push(FunctionState::new(kind, Some(name)));
+        // Never ended, the whole frame is discarded on return
+        self.begin_scope();
+
+        self.parser.consume(TokenType::LeftParen, "Expected '(' after function name");
+        let mut arity: usize = 0;
+        if !self.parser.check(TokenType::RightParen) {
+            loop {
+                if arity == u8::MAX as usize {
+                    self.parser.error_at_current("Can't have more than 255 parameters");
+                }
+                if self.parser.match_token(TokenType::DotDotDot) {
+                    self.require_extension("Rest parameters are not part of standard Lox");
+                    let constant = self.parse_variable("Expected rest parameter name");
+                    self.define_variable(constant);
+                    self.state().function.variadic = true;
+
+                    if self.parser.check(TokenType::Comma) {
+                        self.parser.error_at_current("Rest parameter must be the last parameter");
+                    }
+                }
+                else {
+                    arity += 1;
+                    let constant = self.parse_variable("Expected parameter name");
+                    self.define_variable(constant);
+                }
+
+                if !self.parser.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.state().function.arity = arity.min(u8::MAX as usize) as u8;
+        self.parser.consume(TokenType::RightParen, "Expected ')' after parameters");
+        self.parser.consume(TokenType::LeftBrace, "Expected '{' before function body");
+        self.block();
+
+        let function = self.end_compiler();
+        self.emit_constant(Value::Function(Rc::new(function)));
+    }
+
+    /// `import x from "path";` declares `x` holding the module's namespace,
+    /// while `import "path";` only runs the module.
+    fn import_declaration(&mut self) {
+        if self.parser.match_token(TokenType::String) {
+            self.module_path();
+            self.emit(OpCode::Pop);
+            self.parser.consume(TokenType::Semicolon, "Expected ';' after import");
+            return;
+        }
+
+        let global = self.parse_variable("Expected module path or name");
+        // Not a keyword anywhere else
+        if self.parser.current.as_ref().is_some_and(|tok| tok.span == "from") {
+            self.parser.advance();
+        }
+        else {
+            self.parser.error_at_current("Expected 'from' after module name");
+        }
+        self.parser.consume(TokenType::String, "Expected module path");
+        self.module_path();
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after import");
+
+        self.define_variable(global);
+    }
+
+    /// Emits the import of the module whose path is the previous token.
+    fn module_path(&mut self) {
+        let path = self.string_value();
+        let path = self.make_constant(Value::String(path));
+        self.emit(OpCode::Import(path));
+    }
+
+    fn var_declaration(&mut self) {
+        if self.parser.match_token(TokenType::LeftBracket) {
+            self.pattern_declaration(TokenType::RightBracket);
+            return;
+        }
+        if self.parser.match_token(TokenType::LeftBrace) {
+            self.pattern_declaration(TokenType::RightBrace);
+            return;
+        }
+
+        let global = self.parse_variable("Expected variable name");
+        if self.parser.match_token(TokenType::Comma) {
+            self.unpack_declaration(global);
+            return;
+        }
+
+        if self.parser.match_token(TokenType::Equal) {
+            self.expression();
+        }
+        else {
+            self.emit(OpCode::Nil);
+        }
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after variable declaration");
+
+        self.define_variable(global);
+    }
+
+    /// `var a, b = list;`, after the first name, defining each variable as
+    /// an element of the list.
+    fn unpack_declaration(&mut self, first: u8) {
+        self.require_extension("Declaring several variables is not part of standard Lox");
+
+        let first_name = self.parser.previous.as_ref().map_or("", |tok| tok.span);
+        let mut variables = vec![(first, first_name)];
+        self.variable_list(&mut variables);
+        self.unpack_initializer(&variables, false);
+    }
+
+    /// `var [a, b] = list;` or `var {a, b} = map;`, after the opening bracket
+    /// or brace. A list is unpacked like in `var a, b = list;`, while each
+    /// variable of a map pattern gets the value for the key with its name.
+    fn pattern_declaration(&mut self, closing: TokenType) {
+        self.require_extension("Destructuring is not part of standard Lox");
+
+        let mut variables = Vec::new();
+        if !self.parser.check(closing) {
+            self.variable_list(&mut variables);
+        }
+        let by_key = closing == TokenType::RightBrace;
+        self.parser.consume(closing, if by_key {
+            "Expected '}' after variable names"
+        }
+        else {
+            "Expected ']' after variable names"
+        });
+        self.unpack_initializer(&variables, by_key);
+    }
+
+    /// Parses and declares comma separated variable names, along with the
+    /// constant holding the name of each global.
+    fn variable_list(&mut self, variables: &mut Vec<(u8, &'s str)>) {
+        loop {
+            if variables.len() == u8::MAX as usize {
+                self.parser.error_at_current("Can't unpack more than 255 values");
+            }
+            let global = self.parse_variable("Expected variable name");
+            let name = self.parser.previous.as_ref().map_or("", |tok| tok.span);
+            variables.push((global, name));
+
+            if !self.parser.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+    }
+
+    /// Compiles the initializer of a declaration of several variables and
+    /// defines them, with the elements of a list or, `by_key`, with the
+    /// values for their names in a map.
+    fn unpack_initializer(&mut self, variables: &[(u8, &'s str)], by_key: bool) {
+        self.parser.consume(TokenType::Equal, "Expected '=' after variable names");
+        self.expression();
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after variable declaration");
+
+        let count = variables.len().min(u8::MAX as usize) as u8;
+        if by_key {
+            for (_, name) in variables {
+                let key = self.identifier_constant(name);
+                self.emit(OpCode::Constant(key));
+            }
+            self.emit(OpCode::UnpackMap(count));
+        }
+        else {
+            self.emit(OpCode::Unpack(count));
+        }
+        self.define_variables(variables);
+    }
+
+    /// Defines variables whose values are on top of the stack, the first
+    /// one lowest.
+    fn define_variables(&mut self, variables: &[(u8, &'s str)]) {
+        let state = self.state();
+        if state.scope_depth > 0 {
+            // The locals are already in place on the stack
+            let depth = state.scope_depth;
+            for local in state.locals.iter_mut().rev().take(variables.len()) {
+                local.depth = Some(depth);
+            }
+            return;
+        }
+        // The last value is on top
+        for (global, _) in variables.iter().rev() {
+            self.emit(OpCode::DefineGlobal(*global));
+        }
+    }
+
+    fn statement(&mut self) {
+        if self.parser.match_token(TokenType::Print) {
+            self.print_statement();
+        }
+        else if self.parser.match_token(TokenType::Return) {
+            self.return_statement();
+        }
+        else if self.parser.match_token(TokenType::Throw) {
+            self.throw_statement();
+        }
+        else if self.parser.match_token(TokenType::Try) {
+            self.try_statement();
+        }
+        else if self.parser.match_token(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        }
+        else if self.parser.match_token(TokenType::Break) {
+            self.jump_statement(true);
+        }
+        else if self.parser.match_token(TokenType::Continue) {
+            self.jump_statement(false);
+        }
+        else if self.parser.check(TokenType::Identifier) && self.parser.check_next(TokenType::Colon) {
+            self.labeled_statement();
+        }
+        else if !self.loop_statement(None) {
+            self.expression_statement();
+        }
+    }
+
+    fn labeled_statement(&mut self) {
+        self.require_extension("Loop labels are not part of standard Lox");
+        self.parser.advance();
+        let label = self.parser.previous.as_ref().map_or("", |tok| tok.span);
+        self.parser.advance();
+
+        if !self.loop_statement(Some(label)) {
+            self.parser.error_at_current("Expected a loop after label");
+        }
+    }
+
+    /// `break` when `is_break`, otherwise `continue`, optionally naming the
+    /// loop to leave. Locals and `try` blocks inside the loop body are
+    /// dropped before jumping.
+    fn jump_statement(&mut self, is_break: bool) {
+        self.require_extension("break and continue are not part of standard Lox");
+        let line = self.parser.previous.as_ref().unwrap().line;
+
+        let target = if self.parser.match_token(TokenType::Identifier) {
+            let label = self.parser.previous.as_ref().map(|tok| tok.span);
+            let target = self.state().loops.iter().rposition(|lp| lp.label == label);
+            if target.is_none() {
+                self.parser.error("No enclosing loop with this label");
+            }
+            target
+        }
+        else {
+            let target = self.state().loops.len().checked_sub(1);
+            if target.is_none() {
+                self.parser.error(if is_break {
+                    "Can't use 'break' outside of a loop"
+                }
+                else {
+                    "Can't use 'continue' outside of a loop"
+                });
+            }
+            target
+        };
+        self.parser.consume(TokenType::Semicolon, if is_break {
+            "Expected ';' after break"
+        }
+        else {
+            "Expected ';' after continue"
+        });
+        let Some(target) = target else {
+            return;
+        };
+
+        let state = self.state();
+        let target_loop = &state.loops[target];
+        let handlers = state.handlers - target_loop.handlers;
+        let locals = state.locals.len() - target_loop.locals;
+        for _ in 0..handlers {
+            self.emit(OpCode::PopHandler);
+        }
+        for _ in 0..locals {
+            self.emit(OpCode::Pop);
+        }
+
+        if is_break {
+            let jump = self.emit_jump(OpCode::Jump);
+            self.state().loops[target].breaks.push(jump);
+        }
+        else if let Some(continue_target) = self.state().loops[target].continue_target {
+            self.emit_loop(continue_target, line);
+        }
+        else {
+            let jump = self.emit_jump(OpCode::Jump);
+            self.state().loops[target].continues.push(jump);
+        }
+    }
+
+    /// Starts the body of a loop that `continue` resumes at
+    /// `continue_target`, or wherever the `continue` jumps are patched to if
+    /// it is `None`.
+    fn begin_loop(&mut self, label: Option<&'s str>, continue_target: Option<usize>) {
+        let state = self.state();
+        let locals = state.locals.len();
+        let handlers = state.handlers;
+        state.loops.push(Loop {
+            label,
+            locals,
+            handlers,
+            continue_target,
+            continues: Vec::new(),
+            breaks: Vec::new(),
+        });
+    }
+
+    /// Points the pending `continue` jumps of the innermost loop here.
+    fn patch_continues(&mut self) {
+        let continues = std::mem::take(&mut self.state().loops.last_mut().unwrap().continues);
+        for jump in continues {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// Ends the innermost loop, pointing its `break` jumps here.
+    fn end_loop(&mut self) {
+        let finished = self.state().loops.pop().unwrap();
+        for jump in finished.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// Compiles a loop if the current token starts one, giving it `label`.
+    fn loop_statement(&mut self, label: Option<&'s str>) -> bool {
+        if self.parser.match_token(TokenType::While) {
+            self.while_statement(label);
+        }
+        else if self.parser.match_token(TokenType::Do) {
+            self.do_statement(label);
+        }
+        else if self.parser.match_token(TokenType::For) {
+            self.for_statement(label);
+        }
+        else {
+            return false;
+        }
+        true
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after value");
+        self.emit(OpCode::Print);
+    }
+
+    fn return_statement(&mut self) {
+        if self.state().kind == FunctionType::Script {
+            self.parser.error("Can't return from top-level code");
+        }
+
+        if self.parser.match_token(TokenType::Semicolon) {
+            self.emit_return();
+        }
+        else {
+            self.expression();
+            if self.parser.check(TokenType::Comma) {
+                self.require_extension("Returning several values is not part of standard Lox");
+            }
+            // Several values are returned as a list
+            let mut count: usize = 1;
+            while self.parser.match_token(TokenType::Comma) {
+                self.expression();
+                if count == u8::MAX as usize {
+                    self.parser.error("Can't return more than 255 values");
+                }
+                count += 1;
+            }
+            if count > 1 {
+                self.emit(OpCode::BuildList(count.min(u8::MAX as usize) as u8));
+            }
+            self.parser.consume(TokenType::Semicolon, "Expected ';' after return value");
+            self.emit(OpCode::Return);
+        }
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after thrown value");
+        self.emit(OpCode::Throw);
+    }
+
+    /// `try { ... } catch (e) { ... }`. The handler gets the thrown value,
+    /// or the message of a runtime error, in a new scope.
+    fn try_statement(&mut self) {
+        let handler_jump = self.emit_jump(OpCode::PushHandler);
+
+        self.parser.consume(TokenType::LeftBrace, "Expected '{' after 'try'");
+        self.state().handlers += 1;
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.state().handlers -= 1;
+
+        self.emit(OpCode::PopHandler);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(handler_jump);
+
+        self.parser.consume(TokenType::Catch, "Expected 'catch' after try block");
+        self.parser.consume(TokenType::LeftParen, "Expected '(' after 'catch'");
+        self.begin_scope();
+        // The thrown value is already on the stack
+        let name = self.parse_variable("Expected variable name");
+        self.define_variable(name);
+        self.parser.consume(TokenType::RightParen, "Expected ')' after variable name");
+        self.parser.consume(TokenType::LeftBrace, "Expected '{' before catch block");
+        self.block();
+        self.end_scope();
+
+        self.patch_jump(end_jump);
+    }
+
+    fn block(&mut self) {
+        while self.parser.current.is_some() && !self.parser.check(TokenType::RightBrace) {
+            self.declaration();
+        }
+        self.parser.consume(TokenType::RightBrace, "Expected '}' after block");
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state().scope_depth -= 1;
+
+        loop {
+            let state = self.state();
+            let out_of_scope = state.locals.last()
+                .is_some_and(|local| local.depth.is_none_or(|depth| depth > state.scope_depth));
+            if !out_of_scope {
+                break;
+            }
+            state.locals.pop();
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    fn while_statement(&mut self, label: Option<&'s str>) {
+        let line = self.parser.previous.as_ref().unwrap().line;
+        let loop_start = self.current_chunk().len();
+
+        self.parser.consume(TokenType::LeftParen, "Expected '(' after 'while'");
+        self.expression();
+        self.parser.consume(TokenType::RightParen, "Expected ')' after condition");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        self.begin_loop(label, Some(loop_start));
+        self.statement();
+        self.emit_loop(loop_start, line);
+
+        self.patch_jump(exit_jump);
+        self.emit_at(OpCode::Pop, line);
+        self.end_loop();
+    }
+
+    /// The body runs before the condition is first checked, so it always
+    /// runs at least once.
+    fn do_statement(&mut self, label: Option<&'s str>) {
+        self.require_extension("do-while loops are not part of standard Lox");
+        let line = self.parser.previous.as_ref().unwrap().line;
+        let loop_start = self.current_chunk().len();
+
+        self.begin_loop(label, None);
+        self.statement();
+        self.patch_continues();
+
+        self.parser.consume(TokenType::While, "Expected 'while' after do-while body");
+        self.parser.consume(TokenType::LeftParen, "Expected '(' after 'while'");
+        self.expression();
+        self.parser.consume(TokenType::RightParen, "Expected ')' after condition");
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after do-while condition");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit(OpCode::Pop);
+        self.emit_loop(loop_start, line);
+
+        self.patch_jump(exit_jump);
+        self.emit_at(OpCode::Pop, line);
+        self.end_loop();
+    }
+
+    /// Desugared onto the same jumps as `while`. The initializer's variable
+    /// is a local scoped to the loop.
+    fn for_statement(&mut self, label: Option<&'s str>) {
+        let line = self.parser.previous.as_ref().unwrap().line;
+        self.begin_scope();
+
+        self.parser.consume(TokenType::LeftParen, "Expected '(' after 'for'");
+        let declares = self.parser.match_token(TokenType::Var);
+        if self.parser.check(TokenType::Identifier) && self.parser.check_next(TokenType::In) {
+            self.for_in_loop(label, line);
+            self.end_scope();
+            return;
+        }
+
+        if declares {
+            self.var_declaration();
+        }
+        else if self.parser.match_token(TokenType::Semicolon) {
+            // No initializer
+        }
+        else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.current_chunk().len();
+
+        let mut exit_jump = None;
+        if !self.parser.match_token(TokenType::Semicolon) {
+            self.expression();
+            self.parser.consume(TokenType::Semicolon, "Expected ';' after loop condition");
+
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit(OpCode::Pop);
+        }
+
+        if !self.parser.match_token(TokenType::RightParen) {
+            // The increment runs after the body, so jump over it and have the
+            // body loop back to it instead
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.current_chunk().len();
+
+            self.expression();
+            self.emit(OpCode::Pop);
+            self.parser.consume(TokenType::RightParen, "Expected ')' after for clauses");
+
+            self.emit_loop(loop_start, line);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.begin_loop(label, Some(loop_start));
+        self.statement();
+        self.emit_loop(loop_start, line);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_at(OpCode::Pop, line);
+        }
+        self.end_loop();
+
+        self.end_scope();
+    }
+
+    /// The rest of `for (x in iterable) body`, after the opening parenthesis.
+    /// The iterable and the state of the iteration live in hidden locals,
+    /// below a new `x` for each element.
+    fn for_in_loop(&mut self, label: Option<&'s str>, line: usize) {
+        self.require_extension("for-in loops are not part of standard Lox");
+
+        self.parser.advance();
+        let name = self.parser.previous.as_ref().map_or("", |tok| tok.span);
+        self.parser.advance();
+
+        self.expression();
+        self.emit(OpCode::Nil);
+        // Unnamed, so the body can't refer to them
+        for _ in 0..2 {
+            self.add_local("");
+            self.mark_initialized();
+        }
+        self.parser.consume(TokenType::RightParen, "Expected ')' after for-in clause");
+
+        let loop_start = self.current_chunk().len();
+        let exit_jump = self.emit_jump(OpCode::ForIter);
+
+        self.begin_loop(label, Some(loop_start));
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start, line);
+
+        // The finished iteration pushes nil instead of an element
+        self.patch_jump(exit_jump);
+        self.emit_at(OpCode::Pop, line);
+        self.end_loop();
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.parser.consume(TokenType::Semicolon, "Expected ';' after expression");
+        self.emit(OpCode::Pop);
+    }
+
+    /// Skips tokens until a likely statement boundary, so one syntax error
+    /// doesn't cascade into many.
+    fn synchronize(&mut self) {
+        self.parser.panic_mode = false;
+
+        while let Some(current) = self.parser.current.as_ref() {
+            if self.parser.previous.as_ref()
+                .is_some_and(|prev| prev.token_type == TokenType::Semicolon)
+            {
+                return;
+            }
+            match current.token_type {
+                TokenType::Class | TokenType::Fun | TokenType::Var |
+                TokenType::For | TokenType::If | TokenType::While | TokenType::Do |
+                TokenType::Print | TokenType::Return | TokenType::Break | TokenType::Continue |
+                TokenType::Throw | TokenType::Try | TokenType::Import => return,
+                _ => {},
+            }
+            self.parser.advance();
+        }
+    }
+
+    fn state(&mut self) -> &mut FunctionState<'s> {
+        self.functions.last_mut().unwrap()
+    }
+
     fn current_chunk(&mut self) -> &mut Chunk {
-        self.compiling_chunk.as_mut().unwrap()
+        &mut self.state().function.chunk
     }
-}
\ No newline at end of file
+}
+
+/// Replaces the escape sequences in the contents of a string literal with
+/// the characters they stand for: `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and
+/// `\u{...}` with the hexadecimal code point of any character. A backslash
+/// followed by any other character is kept as it is, so `"\q"` is a
+/// backslash and a `q`; only malformed `\u` escapes are errors.
+fn unescape(contents: &str) -> Result<String, &'static str> {
+    let mut value = String::with_capacity(contents.len());
+    let mut chars = contents.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            value.push(ch);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('u') => {
+                let digits = chars.as_str()
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(digits, _)| digits)
+                    .filter(|digits| (1..=6).contains(&digits.len()))
+                    .ok_or("Invalid unicode escape")?;
+                let code = u32::from_str_radix(digits, 16).map_err(|_| "Invalid unicode escape")?;
+                // The braces and the digits between them
+                chars.nth(digits.len() + 1);
+                char::from_u32(code).ok_or("Invalid unicode escape")?
+            },
+            Some(other) => {
+                value.push('\\');
+                other
+            },
+            None => '\\',
+        };
+        value.push(escaped);
+    }
+    Ok(value)
+}
@@ -1,35 +1,490 @@
+use std::fmt::{self, Write};
+use std::io;
+use std::ops;
+
 use crate::chunk::{OpCode, Chunk};
+use crate::object::Function;
+use crate::value::Value;
 
 pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    eprintln!("== {name} ==");
+    eprint!("{}", chunk_to_string(chunk, name));
+}
+
+pub fn disassemble_chunk_with_source(chunk: &Chunk, name: &str, source: &str) {
+    eprint!("{}", chunk_with_source_to_string(chunk, name, source));
+}
+
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) {
+    eprint!("{}", instruction_to_string(chunk, offset));
+}
+
+pub fn chunk_to_string(chunk: &Chunk, name: &str) -> String {
+    let mut out = String::new();
+    write_chunk(&mut out, chunk, name).unwrap();
+    out
+}
+
+pub fn chunk_with_source_to_string(chunk: &Chunk, name: &str, source: &str) -> String {
+    let mut out = String::new();
+    write_chunk_with_source(&mut out, chunk, name, source).unwrap();
+    out
+}
+
+pub fn instruction_to_string(chunk: &Chunk, offset: usize) -> String {
+    let mut out = String::new();
+    write_instruction(&mut out, chunk, offset).unwrap();
+    out
+}
+
+pub fn write_chunk<W: Write>(out: &mut W, chunk: &Chunk, name: &str) -> fmt::Result {
+    writeln!(out, "== {name} ==")?;
 
     for (offset, _) in chunk.code().iter().enumerate() {
-        disassemble_instruction(chunk, offset);
+        write_instruction(out, chunk, offset)?;
     }
+
+    for function in functions(chunk) {
+        writeln!(out)?;
+        write_chunk(out, &function.chunk, function.name())?;
+    }
+    Ok(())
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) {
+/// Functions in the constant table, whose code is written after the code of
+/// the chunk declaring them.
+fn functions(chunk: &Chunk) -> impl Iterator<Item = &Function> {
+    chunk.constants().iter().filter_map(|constant| match constant {
+        Value::Function(function) => Some(&**function),
+        _ => None,
+    })
+}
+
+/// Like `write_chunk`, but prints each source line above the instructions
+/// generated for it.
+pub fn write_chunk_with_source<W: Write>(
+    out: &mut W, chunk: &Chunk, name: &str, source: &str
+) -> fmt::Result {
+    writeln!(out, "== {name} ==")?;
+
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut previous_line = None;
+
+    for (offset, _) in chunk.code().iter().enumerate() {
+        let line = chunk.lines()[offset];
+        if previous_line != Some(line) {
+            if let Some(text) = line.checked_sub(1).and_then(|idx| source_lines.get(idx)) {
+                writeln!(out, ";; {line}: {}", text.trim())?;
+            }
+            previous_line = Some(line);
+        }
+        write_instruction(out, chunk, offset)?;
+    }
+
+    for function in functions(chunk) {
+        writeln!(out)?;
+        write_chunk_with_source(out, &function.chunk, function.name(), source)?;
+    }
+    Ok(())
+}
+
+pub fn write_instruction<W: Write>(out: &mut W, chunk: &Chunk, offset: usize) -> fmt::Result {
     let instruction = &chunk.code()[offset];
-    eprint!("{offset:04} ");
+    write!(out, "{offset:04} ")?;
 
     let current_line = chunk.lines()[offset];
     if offset > 0 && current_line == chunk.lines()[offset - 1] {
-        eprint!("   | ");
+        write!(out, "   | ")?;
     }
     else {
-        eprint!("{:4} ", current_line);
+        write!(out, "{:4} ", current_line)?;
     }
 
+    write_operation(out, chunk, offset, instruction)?;
+    writeln!(out)
+}
+
+fn write_operation<W: Write>(
+    out: &mut W, chunk: &Chunk, offset: usize, instruction: &OpCode
+) -> fmt::Result {
     match instruction {
         OpCode::Return | OpCode::Negate |
         OpCode::Add | OpCode::Substract |
-        OpCode::Multiply | OpCode::Divide |
+        OpCode::Multiply | OpCode::Divide | OpCode::Modulo |
+        OpCode::BitAnd | OpCode::BitOr | OpCode::BitXor |
+        OpCode::ShiftLeft | OpCode::ShiftRight | OpCode::BitNot |
         OpCode::Nil | OpCode::True | OpCode::False |
-        OpCode::Not | OpCode::Equal | OpCode::Greater | OpCode::Less
-            => eprintln!("{:?}", instruction),
-        OpCode::Constant(id) => {
+        OpCode::Not | OpCode::Equal |
+        OpCode::Greater | OpCode::GreaterEqual |
+        OpCode::Less | OpCode::LessEqual |
+        OpCode::Pop | OpCode::Print |
+        OpCode::GetIndex | OpCode::SetIndex | OpCode::Slice | OpCode::Range |
+        OpCode::PopHandler | OpCode::Throw |
+        OpCode::GetLocal(_) | OpCode::SetLocal(_) |
+        OpCode::Call(_) | OpCode::BuildList(_) | OpCode::BuildMap(_) |
+        OpCode::Unpack(_) | OpCode::UnpackMap(_)
+            => write!(out, "{:?}", instruction),
+        OpCode::Jump(_) | OpCode::JumpIfFalse(_) | OpCode::Loop(_) |
+        OpCode::ForIter(_) | OpCode::PushHandler(_) => {
+            write!(out, "{:?}", instruction)?;
+            if let Some(target) = instruction.jump_target(offset) {
+                write!(out, " -> {:04}", target)?;
+            }
+            Ok(())
+        },
+        OpCode::Constant(id) | OpCode::DefineGlobal(id) |
+        OpCode::GetGlobal(id) | OpCode::SetGlobal(id) | OpCode::Import(id) => {
             let val = &chunk.constants()[*id as usize];
-            eprintln!("{:?} {:?}", instruction, val);
+            write!(out, "{:?} {:?}", instruction, val)
+        },
+    }
+}
+
+pub fn write_json_trace<W: io::Write>(
+    out: &mut W, chunk: &Chunk, ip: usize, stack: &[Value]
+) -> io::Result<()> {
+    let instruction = &chunk.code()[ip];
+
+    write!(out, "{{\"ip\":{ip},\"opcode\":\"{}\",\"operands\":[", instruction.name())?;
+    for (i, operand) in instruction.operands().iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{operand}")?;
+    }
+
+    write!(out, "],\"stack\":[")?;
+    for (i, value) in stack.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write_json_value(out, value)?;
+    }
+
+    writeln!(out, "],\"line\":{}}}", chunk.lines()[ip])
+}
+
+fn write_json_value<W: io::Write>(out: &mut W, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Nil => write!(out, "null"),
+        Value::Bool(val) => write!(out, "{val}"),
+        // JSON has no representation for NaN or the infinities
+        Value::Number(val) if val.is_finite() => write!(out, "{val}"),
+        Value::Number(val) => write!(out, "\"{val}\""),
+        Value::Int(val) => write!(out, "{val}"),
+        Value::String(val) => write_json_string(out, val),
+        Value::Function(_) | Value::NativeFn(_) |
+        Value::List(_) | Value::Map(_) | Value::Range { .. } => {
+            write_json_string(out, &value.to_string())
         },
+    }
+}
+
+fn write_json_string<W: io::Write>(out: &mut W, string: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for ch in string.chars() {
+        match ch {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            ch if ch.is_control() => write!(out, "\\u{:04x}", ch as u32)?,
+            ch => write!(out, "{ch}")?,
+        }
+    }
+    write!(out, "\"")
+}
+
+/// How control leaves an instruction, used to split a chunk into basic blocks.
+enum Exit {
+    Fallthrough,
+    /// Continues at the target when the condition is false, otherwise falls
+    /// through.
+    Branch(usize),
+    /// Falls through, and continues at the target if a value is thrown.
+    Handler(usize),
+    Jump(usize),
+    Halt,
+}
+
+fn exit_of(instruction: &OpCode, offset: usize) -> Exit {
+    match (instruction, instruction.jump_target(offset)) {
+        (OpCode::Return | OpCode::Throw, _) => Exit::Halt,
+        (OpCode::JumpIfFalse(_) | OpCode::ForIter(_), Some(target)) => Exit::Branch(target),
+        (OpCode::PushHandler(_), Some(target)) => Exit::Handler(target),
+        (OpCode::Jump(_) | OpCode::Loop(_), Some(target)) => Exit::Jump(target),
+        _ => Exit::Fallthrough,
+    }
+}
+
+/// Partitions the chunk into basic blocks, returned as ranges of offsets.
+pub fn basic_blocks(chunk: &Chunk) -> Vec<ops::Range<usize>> {
+    let code = chunk.code();
+    let mut leaders = vec![false; code.len() + 1];
+    leaders[0] = true;
+    leaders[code.len()] = true;
+
+    for (offset, instruction) in code.iter().enumerate() {
+        match exit_of(instruction, offset) {
+            Exit::Fallthrough => {},
+            Exit::Branch(target) | Exit::Handler(target) | Exit::Jump(target) => {
+                leaders[offset + 1] = true;
+                if target <= code.len() {
+                    leaders[target] = true;
+                }
+            },
+            Exit::Halt => leaders[offset + 1] = true,
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (offset, &leader) in leaders.iter().enumerate().skip(1) {
+        if leader {
+            if start < offset {
+                blocks.push(start..offset);
+            }
+            start = offset;
+        }
+    }
+    blocks
+}
+
+pub fn cfg_to_string(chunk: &Chunk, name: &str) -> String {
+    let mut out = String::new();
+    write_cfg_dot(&mut out, chunk, name).unwrap();
+    out
+}
+
+/// Writes the control-flow graph of the chunk in Graphviz dot format,
+/// labelling each basic block with its disassembly. The chunk and each
+/// function in its constant tables get a cluster of their own.
+pub fn write_cfg_dot<W: Write>(out: &mut W, chunk: &Chunk, name: &str) -> fmt::Result {
+    writeln!(out, "digraph \"{}\" {{", escape_dot(name))?;
+    writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+    write_cfg_cluster(out, chunk, name, &mut 0)?;
+    writeln!(out, "}}")
+}
+
+/// Writes the blocks of a chunk as cluster number `next_cluster`, followed
+/// by the clusters of its functions, which take the following numbers.
+fn write_cfg_cluster<W: Write>(
+    out: &mut W, chunk: &Chunk, name: &str, next_cluster: &mut usize
+) -> fmt::Result {
+    let cluster = *next_cluster;
+    *next_cluster += 1;
+
+    let blocks = basic_blocks(chunk);
+    let block_at = |offset: usize| blocks.iter().position(|b| b.start == offset);
+
+    writeln!(out, "    subgraph cluster{cluster} {{")?;
+    writeln!(out, "        label=\"{}\";", escape_dot(name))?;
+
+    for (id, block) in blocks.iter().enumerate() {
+        let mut label = String::new();
+        for offset in block.clone() {
+            write_instruction(&mut label, chunk, offset)?;
+        }
+        let label = escape_dot(&label).replace('\n', "\\l");
+        writeln!(out, "        c{cluster}b{id} [label=\"{label}\"];")?;
+    }
+
+    for (id, block) in blocks.iter().enumerate() {
+        let last = &chunk.code()[block.end - 1];
+        let fallthrough = block_at(block.end);
+        let edge = |target: usize, label: Option<&str>| {
+            let label = label.map(|label| format!(" [label=\"{label}\"]")).unwrap_or_default();
+            format!("        c{cluster}b{id} -> c{cluster}b{target}{label};")
+        };
+        match exit_of(last, block.end - 1) {
+            Exit::Fallthrough => {
+                if let Some(next) = fallthrough {
+                    writeln!(out, "{}", edge(next, None))?;
+                }
+            },
+            Exit::Branch(target) => {
+                // `for` loops go on with the next element, or jump out when
+                // there are none left
+                let (stay, leave) = match last {
+                    OpCode::ForIter(_) => ("next", "done"),
+                    _ => ("true", "false"),
+                };
+                if let Some(next) = fallthrough {
+                    writeln!(out, "{}", edge(next, Some(stay)))?;
+                }
+                if let Some(target) = block_at(target) {
+                    writeln!(out, "{}", edge(target, Some(leave)))?;
+                }
+            },
+            Exit::Handler(target) => {
+                if let Some(next) = fallthrough {
+                    writeln!(out, "{}", edge(next, None))?;
+                }
+                if let Some(target) = block_at(target) {
+                    writeln!(out, "{}", edge(target, Some("throw")))?;
+                }
+            },
+            Exit::Jump(target) => {
+                if let Some(target) = block_at(target) {
+                    writeln!(out, "{}", edge(target, None))?;
+                }
+            },
+            Exit::Halt => {},
+        }
+    }
+    writeln!(out, "    }}")?;
+
+    for function in functions(chunk) {
+        write_cfg_cluster(out, &function.chunk, function.name(), next_cluster)?;
+    }
+    Ok(())
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Longest-common-subsequence alignment of two sequences.
+fn align<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            }
+            else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.push(Edit::Same(i, j));
+            i += 1;
+            j += 1;
+        }
+        else if i < a.len() && (j == b.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            edits.push(Edit::Removed(i));
+            i += 1;
+        }
+        else {
+            edits.push(Edit::Added(j));
+            j += 1;
+        }
+    }
+    edits
+}
+
+/// Instructions are compared by opcode and resolved constant rather than
+/// by constant index, so renumbered constants don't show up as changes.
+fn instruction_key(chunk: &Chunk, instruction: &OpCode) -> String {
+    match instruction {
+        OpCode::Constant(id) | OpCode::DefineGlobal(id) |
+        OpCode::GetGlobal(id) | OpCode::SetGlobal(id) | OpCode::Import(id) => {
+            format!("{} {:?}", instruction.name(), chunk.constants()[*id as usize])
+        },
+        _ => format!("{:?}", instruction),
+    }
+}
+
+/// Constants are compared by value, except functions which are compared by
+/// name, since their code is diffed separately.
+fn constant_key(constant: &Value) -> String {
+    format!("{:?}", constant)
+}
+
+/// Writes an aligned diff of the instructions and constants of two chunks,
+/// followed by diffs of the functions in their constant tables. Functions
+/// are paired up by name, and one without a counterpart is diffed against
+/// an empty chunk.
+pub fn write_chunk_diff<W: Write>(out: &mut W, old: &Chunk, new: &Chunk) -> fmt::Result {
+    write_function_diff(out, old, new, None)
+}
+
+/// Writes the diff of the chunks of the function at `path`, a dotted list of
+/// the names of the functions enclosing it, or of the script if `None`.
+fn write_function_diff<W: Write>(
+    out: &mut W, old: &Chunk, new: &Chunk, path: Option<&str>
+) -> fmt::Result {
+    let old_keys: Vec<String> = old.code().iter()
+        .map(|op| instruction_key(old, op))
+        .collect();
+    let new_keys: Vec<String> = new.code().iter()
+        .map(|op| instruction_key(new, op))
+        .collect();
+    let section = |name: &str| match path {
+        Some(path) => format!("== {path}: {name} =="),
+        None => format!("== {name} =="),
     };
-}
\ No newline at end of file
+
+    if path.is_some() {
+        writeln!(out)?;
+    }
+    writeln!(out, "{}", section("code"))?;
+    for edit in align(&old_keys, &new_keys) {
+        match edit {
+            Edit::Same(i, j) => {
+                write!(out, "  {i:04} {j:04} ")?;
+                write_operation(out, new, j, &new.code()[j])?;
+            },
+            Edit::Removed(i) => {
+                write!(out, "- {i:04}      ")?;
+                write_operation(out, old, i, &old.code()[i])?;
+            },
+            Edit::Added(j) => {
+                write!(out, "+      {j:04} ")?;
+                write_operation(out, new, j, &new.code()[j])?;
+            },
+        }
+        writeln!(out)?;
+    }
+
+    let old_constants: Vec<String> = old.constants().iter().map(constant_key).collect();
+    let new_constants: Vec<String> = new.constants().iter().map(constant_key).collect();
+
+    writeln!(out, "{}", section("constants"))?;
+    for edit in align(&old_constants, &new_constants) {
+        match edit {
+            Edit::Same(i, j) => writeln!(out, "  {i:3} {j:3} {}", new_constants[j])?,
+            Edit::Removed(i) => writeln!(out, "- {i:3}     {}", old_constants[i])?,
+            Edit::Added(j) => writeln!(out, "+     {j:3} {}", new_constants[j])?,
+        }
+    }
+
+    let empty = Chunk::new();
+    let mut unmatched: Vec<&Function> = functions(new).collect();
+    for function in functions(old) {
+        // The first function of the same name not yet paired up
+        let counterpart = unmatched.iter()
+            .position(|other| other.name() == function.name())
+            .map(|index| unmatched.remove(index));
+        let path = qualified_name(path, function);
+        let new_chunk = counterpart.map_or(&empty, |other| &other.chunk);
+        write_function_diff(out, &function.chunk, new_chunk, Some(&path))?;
+    }
+    for function in unmatched {
+        let path = qualified_name(path, function);
+        write_function_diff(out, &empty, &function.chunk, Some(&path))?;
+    }
+    Ok(())
+}
+
+fn qualified_name(path: Option<&str>, function: &Function) -> String {
+    match path {
+        Some(path) => format!("{}.{}", path, function.name()),
+        None => function.name().to_owned(),
+    }
+}
+
+pub fn chunk_diff_to_string(old: &Chunk, new: &Chunk) -> String {
+    let mut out = String::new();
+    write_chunk_diff(&mut out, old, new).unwrap();
+    out
+}
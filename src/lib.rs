@@ -1,6 +1,77 @@
+//! A bytecode interpreter for the Lox language from
+//! [Crafting Interpreters](https://craftinginterpreters.com/).
+//!
+//! Embedding the interpreter only takes a [`VM`]:
+//!
+//! ```
+//! use m2_rslox::prelude::*;
+//!
+//! let mut vm = VM::new();
+//! assert!(vm.interpret("var answer = (1 + 2) * 3;").is_ok());
+//! assert!(vm.interpret("print answer;").is_ok());
+//! assert_eq!(vm.interpret("print answer + nil;"), Err(InterpretResult::RuntimeError));
+//! assert_eq!(vm.interpret("print 1 +;"), Err(InterpretResult::CompileError));
+//! ```
+//!
+//! Source can also be compiled ahead of time and inspected before running it:
+//!
+//! ```
+//! use m2_rslox::prelude::*;
+//! use m2_rslox::debug;
+//!
+//! let chunk = Compiler::new("print -(1 + 2);").compile().unwrap();
+//! assert_eq!(chunk.len(), 7);
+//! assert!(debug::chunk_to_string(&chunk, "script").contains("Negate"));
+//!
+//! let mut vm = VM::new();
+//! assert!(vm.interpret_chunk(chunk).is_ok());
+//! ```
+
+pub mod asm;
+pub mod batch;
+pub mod bytecode;
+pub mod cache;
 pub mod chunk;
 pub mod compiler;
 pub mod debug;
+mod natives;
+mod object;
+pub mod profile;
+pub mod replay;
 mod scanner;
 mod value;
-pub mod vm;
\ No newline at end of file
+pub mod vm;
+
+pub use asm::AssembleError;
+pub use bytecode::DecodeError;
+pub use cache::ChunkCache;
+pub use chunk::{Chunk, OpCode, VerifyError};
+pub use compiler::{CompileError, Compiler, CompilerOptions};
+pub use natives::{NativeContext, NativePackage};
+pub use object::{ContextNativeFn, Function, Native, NativeFn};
+pub use replay::{Recording, RecordingError};
+pub use value::{IndexError, NumericError, TypeError, Value};
+pub use vm::{InterpretResult, InterruptHandle, VMBuilder, VM};
+
+/// Entry points for the fuzz targets in `fuzz/`. Not part of the public API.
+#[doc(hidden)]
+pub mod fuzzing {
+    use crate::scanner::Scanner;
+
+    /// Scans the whole source, returning the number of tokens and errors.
+    pub fn scan(source: &str) -> usize {
+        let mut scanner = Scanner::new(source);
+        let mut count = 0;
+        while !matches!(scanner.scan_token(), Ok(None)) {
+            count += 1;
+        }
+        count
+    }
+}
+
+/// The types most embedders need, for glob importing.
+pub mod prelude {
+    pub use crate::{
+        Chunk, CompileError, Compiler, CompilerOptions, InterpretResult, Value, VMBuilder, VM,
+    };
+}
@@ -1,29 +1,301 @@
+use std::cell::RefCell;
 use std::env;
 use std::fs;
 use std::io;
+use std::path::PathBuf;
 use std::process;
+use std::rc::Rc;
 
+use m2_rslox::asm;
+use m2_rslox::batch;
+use m2_rslox::bytecode;
+use m2_rslox::cache::ChunkCache;
+use m2_rslox::chunk::Chunk;
+use m2_rslox::compiler::{Compiler, CompilerOptions};
+use m2_rslox::debug;
+use m2_rslox::replay::Recording;
 use m2_rslox::vm::InterpretResult;
-use m2_rslox::vm::VM;
+use m2_rslox::vm::{TraceLevel, VMBuilder, VM};
+use m2_rslox::Value;
 
 fn main() {
-    let argv: Vec<String> = env::args().collect();
+    let mut argv = env::args();
+    let program = argv.next().unwrap_or_default();
 
-    let mut vm = VM::new();
+    let mut argv = argv.peekable();
+    match argv.peek().map(String::as_str) {
+        Some("disasm") => {
+            argv.next();
+            disasm(&program, argv.collect());
+        },
+        Some("compile") => {
+            argv.next();
+            compile_files(&program, argv.collect());
+        },
+        Some("asm") => {
+            argv.next();
+            run_assembly(&program, argv.collect());
+        },
+        _ => {},
+    }
 
-    match argv.len() {
-        1 => repl(&mut vm),
-        2 => run_file(&mut vm, &argv[1]),
-        _ => {
-            eprintln!("Usage: {} [path]", argv[0]);
-            process::exit(64);
+    let mut builder = VM::builder();
+    let mut options = CompilerOptions::default();
+    let mut path = None;
+    let mut profile = false;
+    let mut cache_dir = None;
+    let mut record = None;
+
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--trace-json" => {
+                let Some(path) = argv.next() else {
+                    usage(&program);
+                };
+                builder = if path == "-" {
+                    builder.json_trace(io::stdout())
+                }
+                else {
+                    let file = fs::File::create(&path).unwrap_or_else(|err| {
+                        eprintln!("Could not create '{}': {}", path, err);
+                        process::exit(74);
+                    });
+                    builder.json_trace(io::BufWriter::new(file))
+                };
+            },
+            "--visualize" => {
+                builder = builder.visualizer(Box::new(|state| eprintln!("{}", state)));
+            },
+            "--strict-numbers" => builder = builder.strict_numbers(true),
+            "--stack-size" => {
+                let Some(size) = argv.next().and_then(|size| size.parse().ok()) else {
+                    usage(&program);
+                };
+                builder = builder.stack_size(size);
+            },
+            "--frame-limit" => {
+                let Some(limit) = argv.next().and_then(|limit| limit.parse().ok()) else {
+                    usage(&program);
+                };
+                builder = builder.frame_limit(limit);
+            },
+            "--seed" => {
+                let Some(seed) = argv.next().and_then(|seed| seed.parse().ok()) else {
+                    usage(&program);
+                };
+                builder = builder.seed(seed);
+            },
+            "--cache-dir" => {
+                let Some(dir) = argv.next() else {
+                    usage(&program);
+                };
+                cache_dir = Some(dir.into());
+            },
+            "--record" => {
+                let Some(path) = argv.next() else {
+                    usage(&program);
+                };
+                record = Some(path);
+            },
+            "--replay" => {
+                let Some(path) = argv.next() else {
+                    usage(&program);
+                };
+                builder = builder.replay(read_recording(&path));
+            },
+            "--cache" => cache_dir = ChunkCache::default_dir(),
+            "--no-cache" => cache_dir = None,
+            "--profile" => profile = true,
+            _ if std_option(&arg, &mut options) => {},
+            _ => {
+                path = Some(arg);
+                break;
+            },
+        }
+    }
+    // Everything after the script's path is for the script
+    let script_args: Vec<_> = argv.map(|arg| Value::String(arg.into())).collect();
+
+    // The prelude is written with extensions
+    builder = builder.prelude(options.extensions)
+        .compiler_options(options)
+        .profiling(profile)
+        .recording(record.is_some())
+        .io(true);
+
+    let args = Value::List(Rc::new(RefCell::new(script_args)));
+    match path {
+        None => {
+            let mut vm = build_vm(builder);
+            vm.define_global("ARGS", args);
+            repl(&mut vm)
+        },
+        Some(path) => {
+            if let Some(dir) = cache_dir {
+                builder = builder.chunk_cache(ChunkCache::new(dir));
+            }
+            let mut vm = build_vm(builder);
+            vm.define_global("ARGS", args);
+            run_file(&mut vm, &path, profile, record.as_deref())
+        },
+    }
+}
+
+/// Builds the VM and makes Ctrl-C interrupt the script it is running
+/// instead of killing the process.
+fn build_vm(builder: VMBuilder) -> VM {
+    let vm = builder.build();
+    let interrupt = vm.interrupt_handle();
+    if let Err(err) = ctrlc::set_handler(move || interrupt.interrupt()) {
+        eprintln!("Could not install the Ctrl-C handler: {}", err);
+    }
+    vm
+}
+
+/// Applies `arg` to `options` if it is a `--std=` flag, returning whether
+/// it was one.
+fn std_option(arg: &str, options: &mut CompilerOptions) -> bool {
+    match arg {
+        "--std=lox" | "--std=extended" => {
+            options.extensions = arg == "--std=extended";
+            true
+        },
+        _ => false,
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [--trace-json file] [--profile] [--visualize]
+                 [--strict-numbers] [--std=lox|extended] [--stack-size n]
+                 [--frame-limit n] [--seed n] [--record file|--replay file]
+                 [--cache|--cache-dir dir|--no-cache] [path [args...]]", program);
+    eprintln!("       {} disasm [--std=lox|extended] [--source|--cfg] path", program);
+    eprintln!("       {} disasm [--std=lox|extended] --diff old new", program);
+    eprintln!("       {} compile [--std=lox|extended] path...", program);
+    eprintln!("       {} asm path", program);
+    process::exit(64);
+}
+
+fn disasm(program: &str, mut args: Vec<String>) -> ! {
+    let mut options = CompilerOptions::default();
+    args.retain(|arg| !std_option(arg, &mut options));
+
+    match args.as_slice() {
+        [path] => {
+            let chunk = compile_file(path, &options);
+            print!("{}", debug::chunk_to_string(&chunk, path));
+        },
+        [flag, path] if flag == "--source" => {
+            let source = read_source(path);
+            let chunk = compile_file(path, &options);
+            print!("{}", debug::chunk_with_source_to_string(&chunk, path, &source));
+        },
+        [flag, path] if flag == "--cfg" => {
+            let chunk = compile_file(path, &options);
+            print!("{}", debug::cfg_to_string(&chunk, path));
+        },
+        [flag, old, new] if flag == "--diff" => {
+            let old = compile_file(old, &options);
+            let new = compile_file(new, &options);
+            print!("{}", debug::chunk_diff_to_string(&old, &new));
+        },
+        _ => usage(program),
+    }
+    process::exit(0);
+}
+
+fn compile_files(program: &str, args: Vec<String>) -> ! {
+    let mut options = CompilerOptions::default();
+    let mut paths = Vec::new();
+
+    for arg in args {
+        if !std_option(&arg, &mut options) {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    if paths.is_empty() {
+        usage(program);
+    }
+
+    let sources = match batch::collect_sources(&paths) {
+        Ok(sources) => sources,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(66);
+        },
+    };
+
+    let mut failed = false;
+    for file in batch::compile_files(&sources, &options) {
+        let written = match &file.result {
+            Ok(bytes) => fs::write(file.output_path(), bytes)
+                .map_err(|err| vec![format!("Could not write output: {}", err)]),
+            Err(diagnostics) => Err(diagnostics.clone()),
+        };
+
+        if let Err(diagnostics) = written {
+            failed = true;
+            for diagnostic in diagnostics {
+                eprintln!("{}: {}", file.path.display(), diagnostic);
+            }
         }
     }
+
+    process::exit(if failed { 65 } else { 0 });
+}
+
+fn run_assembly(program: &str, args: Vec<String>) -> ! {
+    let [path] = args.as_slice() else {
+        usage(program);
+    };
+    let text = read_source(path);
+
+    let chunk = match asm::assemble(&text) {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(65);
+        },
+    };
+    // Hand-written code may not be safe to run
+    if let Err(err) = chunk.verify() {
+        eprintln!("{}: {}", path, err);
+        process::exit(65);
+    }
+
+    match VM::new().interpret_chunk(chunk) {
+        Ok(_) => process::exit(0),
+        Err(_) => process::exit(70),
+    }
+}
+
+/// The contents of the file at `path`, exiting if it can't be read.
+fn read_source(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| read_error(path, err))
+}
+
+fn read_error(path: &str, err: io::Error) -> ! {
+    eprintln!("Could not read '{}': {}", path, err);
+    process::exit(74);
+}
+
+fn compile_file(path: &str, options: &CompilerOptions) -> Chunk {
+    let source = read_source(path);
+
+    match Compiler::with_options(&source, options.clone()).compile() {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(65);
+        },
+    }
 }
 
 fn repl(vm: &mut VM) {
     let stdin = io::stdin();
     let mut buf = String::new();
+    // Set by `:dis` for the next input
+    let mut disassemble = false;
 
     loop {
         buf.clear();
@@ -32,23 +304,145 @@ fn repl(vm: &mut VM) {
         let bytes_read = stdin.read_line(&mut buf).unwrap();
 
         if bytes_read == 0 {
-            eprintln!("");
+            eprintln!();
             process::exit(0);
         }
 
-        vm.interpret(&buf);
+        if let Some(command) = buf.trim().strip_prefix(':') {
+            repl_command(vm, command, &mut disassemble);
+            continue;
+        }
+
+        let result = if std::mem::take(&mut disassemble) {
+            disassemble_and_run(vm, &buf)
+        }
+        else {
+            vm.interpret(&buf)
+        };
+        if let Err(InterpretResult::Exit(code)) = result {
+            process::exit(code);
+        }
+    }
+}
+
+fn repl_command(vm: &mut VM, command: &str, disassemble: &mut bool) {
+    let mut words = command.split_whitespace();
+
+    match words.next() {
+        Some("trace") => trace_command(vm, words.collect()),
+        Some("dis") => *disassemble = true,
+        _ => eprintln!("Unknown command ':{}'", command),
+    }
+}
+
+/// Compiles a REPL input, printing its chunk interleaved with the input
+/// before running it.
+fn disassemble_and_run(vm: &mut VM, source: &str) -> Result<(), InterpretResult> {
+    let chunk = match Compiler::with_options(source, vm.compiler_options().clone()).compile() {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            for diagnostic in err.diagnostics {
+                eprintln!("{}", diagnostic);
+            }
+            return Err(InterpretResult::CompileError);
+        },
+    };
+    debug::disassemble_chunk_with_source(&chunk, "input", source);
+    vm.interpret_chunk(chunk)
+}
+
+fn trace_command(vm: &mut VM, args: Vec<&str>) {
+    let mut options = vm.trace_options().clone();
+
+    match args.as_slice() {
+        [] => {},
+        ["off"] => options.level = TraceLevel::Off,
+        ["on"] | ["stack"] => options.level = TraceLevel::Stack,
+        ["instructions"] => options.level = TraceLevel::Instructions,
+        ["only", opcodes @ ..] => {
+            options.opcodes = opcodes.iter()
+                .flat_map(|ops| ops.split(','))
+                .filter(|op| !op.is_empty())
+                .map(String::from)
+                .collect();
+        },
+        ["in"] => options.function = None,
+        ["in", function] => options.function = Some(function.to_string()),
+        ["after", count] => match count.parse() {
+            Ok(count) => options.after = count,
+            Err(_) => {
+                eprintln!("Expected an instruction count, got '{}'", count);
+                return;
+            },
+        },
+        _ => {
+            eprintln!("Usage: :trace [off|on|instructions|stack|only OPS|in [FUNCTION]|after N]");
+            return;
+        },
     }
+
+    eprintln!("{:?}", options);
+    vm.set_trace_options(options);
 }
 
-fn run_file(vm: &mut VM, path: &str) {
-    let source = fs::read_to_string(path).unwrap();
+fn run_file(vm: &mut VM, path: &str, profile: bool, record: Option<&str>) {
+    vm.set_script_path(Some(path.into()));
+    let result = if path.ends_with(".loxc") {
+        run_compiled(vm, path)
+    }
+    else {
+        let source = read_source(path);
+
+        let result = vm.interpret(&source);
 
-    let result: Result<(), InterpretResult> = vm.interpret(&source);
+        if profile {
+            if let Some(profile) = vm.take_profile() {
+                eprint!("{}", profile.annotated_to_string(&source));
+            }
+        }
+        result
+    };
+
+    if let (Some(record), Some(recording)) = (record, vm.take_recording()) {
+        if let Err(err) = fs::write(record, recording.encode()) {
+            eprintln!("Could not write the recording: {}", err);
+            process::exit(74);
+        }
+    }
 
     let exit_code = match result {
         Ok(_) => 0,
         Err(InterpretResult::CompileError) => 65,
         Err(InterpretResult::RuntimeError) => 70,
+        Err(InterpretResult::InternalError) => 70,
+        Err(InterpretResult::Interrupted) => 130,
+        Err(InterpretResult::Exit(code)) => code,
     };
     process::exit(exit_code);
-}
\ No newline at end of file
+}
+
+fn run_compiled(vm: &mut VM, path: &str) -> Result<(), InterpretResult> {
+    let bytes = fs::read(path).unwrap_or_else(|err| read_error(path, err));
+
+    let chunk = match bytecode::decode(&bytes) {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(65);
+        },
+    };
+
+    vm.interpret_chunk(chunk)
+}
+
+/// The recording at `path` for `--replay`, exiting if it can't be read.
+fn read_recording(path: &str) -> Recording {
+    let bytes = fs::read(path).unwrap_or_else(|err| read_error(path, err));
+    match Recording::decode(&bytes) {
+        Ok(recording) => recording,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(65);
+        },
+    }
+}
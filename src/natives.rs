@@ -0,0 +1,832 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::LazyLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+use crate::object::ContextNativeFn;
+use crate::replay::{Input, Recording};
+use crate::value::{self, Value};
+use crate::vm::{CallFrame, InterruptHandle};
+
+/// When the first VM was created, which `clock` and `nanos` count from.
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Global functions every VM starts with, by name.
+pub(crate) const NATIVES: &[(&str, ContextNativeFn)] = &[
+    ("clock", clock),
+    ("nanos", nanos),
+    ("now", now),
+    ("formatTime", format_time),
+    ("parseTime", parse_time),
+    ("random", random),
+    ("randomInt", random_int),
+    ("seed", seed),
+    ("type", type_of),
+    ("str", str),
+    ("num", num),
+    ("isNan", is_nan),
+    ("isFinite", is_finite),
+    ("ord", ord),
+    ("chr", chr),
+    ("deepEqual", deep_equal),
+    ("clone", clone),
+    ("push", push),
+    ("stacktrace", stacktrace),
+    #[cfg(feature = "regex")]
+    ("reMatch", re_match),
+    #[cfg(feature = "regex")]
+    ("reFind", re_find),
+    #[cfg(feature = "regex")]
+    ("reReplace", re_replace),
+];
+
+/// Namespaces of natives that scripts import by name, as in
+/// `import math from "std:math";`, instead of finding them among the
+/// globals. Each is only built when first imported.
+pub(crate) const NATIVE_MODULES: &[(&str, &[(&str, ContextNativeFn)])] = &[
+    ("math", &[
+        ("abs", abs),
+        ("floor", floor),
+        ("ceil", ceil),
+        ("round", round),
+        ("sqrt", sqrt),
+        ("pow", pow),
+        ("min", min),
+        ("max", max),
+    ]),
+    ("str", &[("str", str), ("num", num), ("ord", ord), ("chr", chr)]),
+    ("time", &[
+        ("clock", clock),
+        ("nanos", nanos),
+        ("now", now),
+        ("formatTime", format_time),
+        ("parseTime", parse_time),
+    ]),
+    ("random", &[("random", random), ("randomInt", random_int), ("seed", seed)]),
+    #[cfg(feature = "regex")]
+    ("re", &[("reMatch", re_match), ("reFind", re_find), ("reReplace", re_replace)]),
+];
+
+/// Global functions that reach outside the VM, such as to the filesystem,
+/// or block the thread running it, defined only when enabled with
+/// [`VM::enable_io`](crate::VM::enable_io), which also makes them
+/// importable from `"std:io"`.
+pub(crate) const IO_NATIVES: &[(&str, ContextNativeFn)] = &[
+    ("readFile", read_file),
+    ("writeFile", write_file),
+    ("appendFile", append_file),
+    ("readLine", read_line),
+    ("readAll", read_all),
+    ("env", env_var),
+    ("cwd", cwd),
+    ("exec", exec),
+    ("exit", exit),
+    ("sleep", sleep),
+];
+
+/// State of a VM that its natives share, such as the random number
+/// generator.
+pub(crate) struct NativeState {
+    /// SplitMix64 state.
+    rng: u64,
+    /// Set by `exit`, for the VM to stop with.
+    pub(crate) exit_code: Option<i32>,
+    /// The VM's, so natives that block can stop early when it is
+    /// interrupted.
+    interrupt: InterruptHandle,
+    tape: Tape,
+}
+
+/// Whether the inputs natives read are being recorded or replayed.
+enum Tape {
+    Off,
+    Recording(Recording),
+    /// Replaying the inputs of a recording from the one at `next`.
+    Replaying { recording: Recording, next: usize },
+}
+
+impl NativeState {
+    pub(crate) fn new(interrupt: InterruptHandle) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self { rng: now, exit_code: None, interrupt, tape: Tape::Off }
+    }
+
+    pub(crate) fn seed(&mut self, seed: u64) {
+        self.rng = seed;
+    }
+
+    /// Starts recording the inputs natives read, and the state of the random
+    /// number generator they start from.
+    pub(crate) fn start_recording(&mut self) {
+        self.tape = Tape::Recording(Recording::new(self.rng));
+    }
+
+    /// The inputs recorded since recording started, stopping it.
+    pub(crate) fn take_recording(&mut self) -> Option<Recording> {
+        match std::mem::replace(&mut self.tape, Tape::Off) {
+            Tape::Recording(recording) => Some(recording),
+            tape => {
+                self.tape = tape;
+                None
+            },
+        }
+    }
+
+    pub(crate) fn replay(&mut self, recording: Recording) {
+        self.rng = recording.seed;
+        self.tape = Tape::Replaying { recording, next: 0 };
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// What natives defined with
+/// [`VM::define_context_native`](crate::VM::define_context_native) can see
+/// of the VM calling them.
+pub struct NativeContext<'vm> {
+    state: &'vm mut NativeState,
+    frames: &'vm [CallFrame],
+}
+
+impl<'vm> NativeContext<'vm> {
+    pub(crate) fn new(state: &'vm mut NativeState, frames: &'vm [CallFrame]) -> Self {
+        Self { state, frames }
+    }
+
+    /// Reads an input from outside the VM with `read`, such as the time or
+    /// a line of standard input. While the VM records its inputs the result
+    /// is recorded under the name of the native, and while it replays a
+    /// recording the recorded result is returned instead of calling `read`.
+    pub fn input(
+        &mut self, native: &str, read: impl FnOnce() -> Result<Value, String>
+    ) -> Result<Value, String> {
+        match &mut self.state.tape {
+            Tape::Off => read(),
+            Tape::Recording(recording) => {
+                let result = read();
+                recording.inputs.push(Input { native: native.to_owned(), result: result.clone() });
+                result
+            },
+            Tape::Replaying { recording, next } => {
+                let Some(input) = recording.inputs.get(*next) else {
+                    return Err(format!("The recording has no more inputs for '{}'.", native));
+                };
+                if input.native != native {
+                    return Err(format!(
+                        "Expected an input for '{}' but the recording has one for '{}'.",
+                        native, input.native
+                    ));
+                }
+                *next += 1;
+                input.result.clone()
+            },
+        }
+    }
+
+    /// The functions being called, innermost first, each with the line it
+    /// is at. The script itself is named "script".
+    pub fn stack_trace(&self) -> Vec<(&str, usize)> {
+        self.frames.iter()
+            .rev()
+            .map(|frame| (frame.function.name(), frame.line()))
+            .collect()
+    }
+}
+
+/// A bundle of natives that another crate can ship and a host can add to a
+/// VM with [`VM::install`](crate::VM::install), such as bindings to a
+/// database or an HTTP client.
+///
+/// ```
+/// use m2_rslox::{NativePackage, Value, VM};
+///
+/// struct Strings;
+///
+/// impl NativePackage for Strings {
+///     fn name(&self) -> &str {
+///         "strings"
+///     }
+///
+///     fn register(&self, vm: &mut VM) {
+///         vm.define_native("upper", |args| match args {
+///             [Value::String(text)] => Ok(Value::String(text.to_uppercase().into())),
+///             _ => Err("Expected a string.".to_owned()),
+///         });
+///     }
+/// }
+///
+/// let mut vm = VM::new();
+/// vm.install(&Strings);
+/// assert!(vm.is_installed("strings"));
+/// assert!(vm.interpret("print upper(\"lox\");").is_ok());
+/// ```
+pub trait NativePackage {
+    /// Identifies the package, so it is installed only once.
+    fn name(&self) -> &str;
+
+    /// Defines the natives of the package, usually with
+    /// [`VM::define_native`](crate::VM::define_native) or
+    /// [`VM::define_context_native`](crate::VM::define_context_native).
+    /// Packages may install the packages they build on first.
+    fn register(&self, vm: &mut crate::VM);
+}
+
+/// Starts the clock of `clock` and `nanos` if it isn't running yet.
+pub(crate) fn start_clock() {
+    LazyLock::force(&START);
+}
+
+fn check_arity(args: &[Value], arity: usize) -> Result<(), String> {
+    if args.len() == arity {
+        Ok(())
+    }
+    else {
+        Err(format!("Expected {} arguments but got {}.", arity, args.len()))
+    }
+}
+
+fn integer_argument(value: &Value) -> Result<i64, String> {
+    value::as_integer(value).ok_or_else(|| {
+        format!("Argument must be an integer; got {} ({}).", value.type_name(), value)
+    })
+}
+
+fn string_argument(value: &Value) -> Result<&str, String> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(format!("Argument must be a string; got {} ({}).", value.type_name(), value)),
+    }
+}
+
+/// Seconds since the start, as a fractional number.
+fn clock(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    context.input("clock", || Ok(Value::Number(START.elapsed().as_secs_f64())))
+}
+
+/// Nanoseconds since the start, as an integer.
+fn nanos(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    context.input("nanos", || {
+        let elapsed = START.elapsed().as_nanos();
+        Ok(Value::Int(i64::try_from(elapsed).unwrap_or(i64::MAX)))
+    })
+}
+
+/// Seconds since the Unix epoch, as a fractional number.
+fn now(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    context.input("now", || {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| "System clock is set before 1970.".to_owned())?;
+        Ok(Value::Number(elapsed.as_secs_f64()))
+    })
+}
+
+/// Seconds since the Unix epoch written as UTC with a `strftime` format,
+/// such as "%Y-%m-%d %H:%M:%S".
+fn format_time(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let epoch = args[0].as_f64().ok_or_else(|| {
+        format!("Argument must be a number; got {} ({}).", args[0].type_name(), args[0])
+    })?;
+    let format = string_argument(&args[1])?;
+
+    let secs = epoch.floor();
+    let nanos = ((epoch - secs) * 1e9) as u32;
+    let time = DateTime::from_timestamp(secs as i64, nanos)
+        .filter(|_| epoch.is_finite())
+        .ok_or_else(|| format!("Time {} out of range.", args[0]))?;
+
+    // Invalid formats only fail once written
+    let mut formatted = String::new();
+    write!(formatted, "{}", time.format(format))
+        .map_err(|_| format!("Invalid time format '{}'.", format))?;
+    Ok(Value::String(formatted.into()))
+}
+
+/// Seconds since the Unix epoch of a UTC time written with a `strftime`
+/// format, or `nil` if the text doesn't match it. Formats without a time of
+/// day give midnight.
+fn parse_time(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let text = string_argument(&args[0])?;
+    let format = string_argument(&args[1])?;
+
+    let time = NaiveDateTime::parse_from_str(text, format).ok().or_else(|| {
+        NaiveDate::parse_from_str(text, format).ok().and_then(|date| date.and_hms_opt(0, 0, 0))
+    });
+    Ok(time.map_or(Value::Nil, |time| {
+        let time = time.and_utc();
+        let subsec = time.timestamp_subsec_nanos();
+        if subsec == 0 {
+            Value::Int(time.timestamp())
+        }
+        else {
+            Value::Number(time.timestamp() as f64 + subsec as f64 / 1e9)
+        }
+    }))
+}
+
+/// A number from 0 up to but not including 1.
+fn random(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    // The top 53 bits fill the mantissa exactly
+    let bits = context.state.next_u64() >> 11;
+    Ok(Value::Number(bits as f64 / (1u64 << 53) as f64))
+}
+
+/// An integer from `lo` up to but not including `hi`, like the range
+/// `lo..hi`.
+fn random_int(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let lo = integer_argument(&args[0])?;
+    let hi = integer_argument(&args[1])?;
+    if hi <= lo {
+        return Err(format!("Range {}..{} is empty.", lo, hi));
+    }
+
+    let span = hi.abs_diff(lo);
+    // Scales the full range of the generator down to the span
+    let offset = ((context.state.next_u64() as u128 * span as u128) >> 64) as u64;
+    Ok(Value::Int(lo.wrapping_add_unsigned(offset)))
+}
+
+fn seed(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let seed = integer_argument(&args[0])?;
+    context.state.seed(seed as u64);
+    Ok(Value::Nil)
+}
+
+/// The name of the type of the argument, such as "number" or "nil".
+fn type_of(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    Ok(Value::String(args[0].type_name().into()))
+}
+
+/// The argument as `print` would write it.
+fn str(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    match &args[0] {
+        string @ Value::String(_) => Ok(string.clone()),
+        value => Ok(Value::String(value.to_string().into())),
+    }
+}
+
+/// The number written in a string, or `nil` if it doesn't hold one. Numbers
+/// are returned unchanged.
+fn num(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let text = match &args[0] {
+        Value::String(text) => text.trim(),
+        number @ (Value::Int(_) | Value::Number(_)) => return Ok(number.clone()),
+        value => return Err(format!(
+            "Argument must be a string or a number; got {} ({}).", value.type_name(), value
+        )),
+    };
+
+    if let Ok(val) = text.parse() {
+        return Ok(Value::Int(val));
+    }
+    // Rust also parses words such as "inf" and "NaN", which aren't numbers
+    // in Lox
+    let numeric = text.chars().all(|ch| ch.is_ascii_digit() || ".eE+-".contains(ch));
+    match text.parse() {
+        Ok(val) if numeric => Ok(Value::Number(val)),
+        _ => Ok(Value::Nil),
+    }
+}
+
+/// The argument as an f64, for natives that look at the float itself.
+fn float_argument(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(val) => Ok(*val),
+        Value::Int(val) => Ok(*val as f64),
+        value => Err(format!("Argument must be a number; got {} ({}).", value.type_name(), value)),
+    }
+}
+
+/// The absolute value of a number. Integers stay integers unless they
+/// overflow.
+fn abs(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    match args[0] {
+        Value::Int(val) => match val.checked_abs() {
+            Some(abs) => Ok(Value::Int(abs)),
+            None => Ok(Value::Number((val as f64).abs())),
+        },
+        _ => Ok(Value::Number(float_argument(&args[0])?.abs())),
+    }
+}
+
+/// Applies `op` to a float, leaving integers, which are already whole,
+/// unchanged.
+fn rounding(args: &[Value], op: fn(f64) -> f64) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    match args[0] {
+        Value::Int(val) => Ok(Value::Int(val)),
+        _ => Ok(Value::Number(op(float_argument(&args[0])?))),
+    }
+}
+
+fn floor(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    rounding(args, f64::floor)
+}
+
+fn ceil(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    rounding(args, f64::ceil)
+}
+
+/// Rounds half-way cases away from zero.
+fn round(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    rounding(args, f64::round)
+}
+
+fn sqrt(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    Ok(Value::Number(float_argument(&args[0])?.sqrt()))
+}
+
+fn pow(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    Ok(Value::Number(float_argument(&args[0])?.powf(float_argument(&args[1])?)))
+}
+
+/// The smaller of two numbers, returned unchanged.
+fn min(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let (a, b) = (float_argument(&args[0])?, float_argument(&args[1])?);
+    Ok(if b < a { args[1].clone() } else { args[0].clone() })
+}
+
+/// The larger of two numbers, returned unchanged.
+fn max(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let (a, b) = (float_argument(&args[0])?, float_argument(&args[1])?);
+    Ok(if b > a { args[1].clone() } else { args[0].clone() })
+}
+
+/// Whether a number is NaN, the only value not equal to itself.
+fn is_nan(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    Ok(Value::Bool(float_argument(&args[0])?.is_nan()))
+}
+
+/// Whether a number is neither infinite nor NaN.
+fn is_finite(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    Ok(Value::Bool(float_argument(&args[0])?.is_finite()))
+}
+
+/// The code point of the character in a string of one character.
+fn ord(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let text = string_argument(&args[0])?;
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Ok(Value::Int(ch as i64)),
+        _ => Err(format!("Argument must be a single character; got {:?}.", text)),
+    }
+}
+
+/// A string of the character with a code point. Surrogates and values past
+/// U+10FFFF aren't characters.
+fn chr(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let code = integer_argument(&args[0])?;
+    if (0xd800..=0xdfff).contains(&code) {
+        return Err(format!("Code point {:#x} is a surrogate.", code));
+    }
+    u32::try_from(code).ok()
+        .and_then(char::from_u32)
+        .map(|ch| Value::String(ch.to_string().into()))
+        .ok_or_else(|| format!("Code point {} out of range.", code))
+}
+
+/// Adds a value to the end of a list.
+fn push(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let Value::List(list) = &args[0] else {
+        return Err(format!(
+            "Argument must be a list; got {} ({}).", args[0].type_name(), args[0]
+        ));
+    };
+    list.borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+/// Whether two values are equal, comparing lists and maps by their elements
+/// rather than by identity.
+fn deep_equal(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    Ok(Value::Bool(values_equal(&args[0], &args[1], &mut Vec::new())))
+}
+
+/// Compares two values element by element. `comparing` holds the pairs of
+/// lists and maps already being compared further out; meeting one of them
+/// again means the values are cyclic in the same place, so that pair is
+/// taken as equal and the rest of the elements decide.
+fn values_equal(a: &Value, b: &Value, comparing: &mut Vec<(*const (), *const ())>) -> bool {
+    let pair = match (a, b) {
+        (Value::List(x), Value::List(y)) => (Rc::as_ptr(x).cast(), Rc::as_ptr(y).cast()),
+        (Value::Map(x), Value::Map(y)) => (Rc::as_ptr(x).cast(), Rc::as_ptr(y).cast()),
+        _ => return a == b,
+    };
+    if pair.0 == pair.1 || comparing.contains(&pair) {
+        return true;
+    }
+
+    comparing.push(pair);
+    let equal = match (a, b) {
+        (Value::List(x), Value::List(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len()
+                && x.iter().zip(y.iter()).all(|(x, y)| values_equal(x, y, comparing))
+        }
+        (Value::Map(x), Value::Map(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().all(|(key, x)| {
+                y.get(key).is_some_and(|y| values_equal(x, y, comparing))
+            })
+        }
+        _ => unreachable!(),
+    };
+    comparing.pop();
+    equal
+}
+
+/// A copy of the argument with its lists and maps copied too, all the way
+/// down. A list or map reached twice is copied once, so shared and cyclic
+/// elements stay shared and cyclic in the copy. Map keys are kept as they
+/// are, since they are looked up by identity.
+fn clone(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    Ok(clone_value(&args[0], &mut HashMap::new()))
+}
+
+/// Copies a value. `copies` maps each list or map already copied to its copy.
+fn clone_value(value: &Value, copies: &mut HashMap<*const (), Value>) -> Value {
+    match value {
+        Value::List(list) => {
+            let key = Rc::as_ptr(list).cast();
+            if let Some(copy) = copies.get(&key) {
+                return copy.clone();
+            }
+            let copy = Rc::new(RefCell::new(Vec::new()));
+            copies.insert(key, Value::List(copy.clone()));
+            let elements: Vec<_> = list.borrow().iter()
+                .map(|element| clone_value(element, copies))
+                .collect();
+            *copy.borrow_mut() = elements;
+            Value::List(copy)
+        }
+        Value::Map(map) => {
+            let key = Rc::as_ptr(map).cast();
+            if let Some(copy) = copies.get(&key) {
+                return copy.clone();
+            }
+            let copy = Rc::new(RefCell::new(Default::default()));
+            copies.insert(key, Value::Map(copy.clone()));
+            let entries = map.borrow().iter()
+                .map(|(key, value)| (key.clone(), clone_value(value, copies)))
+                .collect();
+            *copy.borrow_mut() = entries;
+            Value::Map(copy)
+        }
+        value => value.clone(),
+    }
+}
+
+/// The calls being run as a list of "function:line" strings, innermost
+/// first, ending with the script.
+fn stacktrace(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    let frames = context.stack_trace()
+        .into_iter()
+        .map(|(name, line)| Value::String(format!("{}:{}", name, line).into()))
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(frames))))
+}
+
+/// The contents of the file at a path.
+fn read_file(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let path = string_argument(&args[0])?;
+    context.input("readFile", || {
+        fs::read_to_string(path)
+            .map(|contents| Value::String(contents.into()))
+            .map_err(|err| format!("Could not read '{}': {}.", path, err))
+    })
+}
+
+/// Replaces the contents of the file at a path with a string, creating the
+/// file if it doesn't exist.
+fn write_file(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let path = string_argument(&args[0])?;
+    let text = string_argument(&args[1])?;
+    fs::write(path, text)
+        .map(|()| Value::Nil)
+        .map_err(|err| format!("Could not write '{}': {}.", path, err))
+}
+
+/// Adds a string to the end of the file at a path, creating the file if it
+/// doesn't exist.
+fn append_file(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let path = string_argument(&args[0])?;
+    let text = string_argument(&args[1])?;
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map(|()| Value::Nil)
+        .map_err(|err| format!("Could not write '{}': {}.", path, err))
+}
+
+/// The next line of standard input without its line ending, or `nil` at
+/// the end of the input.
+fn read_line(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    context.input("readLine", || {
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => Ok(Value::Nil),
+            Ok(_) => {
+                let end = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(end);
+                Ok(Value::String(line.into()))
+            },
+            Err(err) => Err(format!("Could not read standard input: {}.", err)),
+        }
+    })
+}
+
+/// The rest of standard input, which is empty at the end of the input.
+fn read_all(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    context.input("readAll", || {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)
+            .map(|_| Value::String(input.into()))
+            .map_err(|err| format!("Could not read standard input: {}.", err))
+    })
+}
+
+/// The value of an environment variable, or `nil` if it isn't set.
+fn env_var(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let name = string_argument(&args[0])?;
+    context.input("env", || match env::var(name) {
+        Ok(value) => Ok(Value::String(value.into())),
+        Err(env::VarError::NotPresent) => Ok(Value::Nil),
+        Err(env::VarError::NotUnicode(_)) => Err(format!("Variable '{}' is not valid UTF-8.", name)),
+    })
+}
+
+/// The directory the interpreter is running in.
+fn cwd(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    context.input("cwd", || {
+        let dir = env::current_dir()
+            .map_err(|err| format!("Could not get the current directory: {}.", err))?;
+        dir.to_str()
+            .map(|dir| Value::String(dir.into()))
+            .ok_or_else(|| format!("Current directory '{}' is not valid UTF-8.", dir.display()))
+    })
+}
+
+/// Runs a program with a list of string arguments and waits for it to
+/// finish, returning a list of its exit status, standard output and
+/// standard error. The status is `nil` if the program was killed by a
+/// signal.
+fn exec(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let program = string_argument(&args[0])?;
+    let Value::List(list) = &args[1] else {
+        return Err(format!(
+            "Argument must be a list; got {} ({}).", args[1].type_name(), args[1]
+        ));
+    };
+    let mut command = Command::new(program);
+    for arg in list.borrow().iter() {
+        command.arg(string_argument(arg)?);
+    }
+
+    context.input("exec", || {
+        let output = command.output()
+            .map_err(|err| format!("Could not run '{}': {}.", program, err))?;
+        let status = output.status.code().map_or(Value::Nil, |code| Value::Int(code.into()));
+        let result = vec![
+            status,
+            Value::String(String::from_utf8_lossy(&output.stdout).into()),
+            Value::String(String::from_utf8_lossy(&output.stderr).into()),
+        ];
+        Ok(Value::List(Rc::new(RefCell::new(result))))
+    })
+}
+
+/// Stops the script, making the interpreter exit with the code.
+fn exit(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let code = integer_argument(&args[0])?;
+    let code = i32::try_from(code).map_err(|_| format!("Exit code {} out of range.", code))?;
+    context.state.exit_code = Some(code);
+    Ok(Value::Nil)
+}
+
+/// Longest stretch `sleep` blocks for before checking for interrupts.
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+/// Blocks for a number of milliseconds, or until the VM is interrupted, in
+/// which case it stops once `sleep` returns.
+fn sleep(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let millis = match args[0].as_f64() {
+        Some(millis) if millis >= 0.0 => millis,
+        _ => return Err(format!(
+            "Argument must be a non-negative number; got {} ({}).", args[0].type_name(), args[0]
+        )),
+    };
+
+    let deadline = Duration::try_from_secs_f64(millis / 1000.0).unwrap_or(Duration::MAX);
+    let start = Instant::now();
+    while !context.state.interrupt.is_interrupted() {
+        let remaining = deadline.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        thread::sleep(remaining.min(SLEEP_SLICE));
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(feature = "regex")]
+fn regex_argument(value: &Value) -> Result<regex::Regex, String> {
+    let pattern = string_argument(value)?;
+    regex::Regex::new(pattern).map_err(|err| {
+        // Syntax errors point at the pattern over several lines, ending with
+        // the actual problem
+        let err = err.to_string();
+        let reason = err.lines().last().unwrap_or_default().trim_start_matches("error: ");
+        format!("Invalid regular expression '{}': {}.", pattern, reason)
+    })
+}
+
+/// Whether a regular expression matches anywhere in a string.
+#[cfg(feature = "regex")]
+fn re_match(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let regex = regex_argument(&args[0])?;
+    let text = string_argument(&args[1])?;
+    Ok(Value::Bool(regex.is_match(text)))
+}
+
+/// The first match of a regular expression in a string as a list of the
+/// whole match followed by each group, `nil` for groups that didn't
+/// match, or `nil` if there is no match.
+#[cfg(feature = "regex")]
+fn re_find(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 2)?;
+    let regex = regex_argument(&args[0])?;
+    let text = string_argument(&args[1])?;
+
+    let Some(captures) = regex.captures(text) else {
+        return Ok(Value::Nil);
+    };
+    let groups = captures.iter()
+        .map(|group| group.map_or(Value::Nil, |group| Value::String(group.as_str().into())))
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(groups))))
+}
+
+/// A string with every match of a regular expression replaced. `$1` or
+/// `${name}` in the replacement stand for the text of a group.
+#[cfg(feature = "regex")]
+fn re_replace(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 3)?;
+    let regex = regex_argument(&args[0])?;
+    let text = string_argument(&args[1])?;
+    let replacement = string_argument(&args[2])?;
+    Ok(Value::String(regex.replace_all(text, replacement).into()))
+}
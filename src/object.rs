@@ -0,0 +1,85 @@
+use std::cell::OnceCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::chunk::Chunk;
+use crate::natives::NativeContext;
+use crate::value::Value;
+use crate::vm::Module;
+
+/// A function implemented by the host, called with its arguments. An error
+/// becomes a runtime error with the message.
+pub type NativeFn = fn(&[Value]) -> Result<Value, String>;
+
+/// Like [`NativeFn`], also given the state natives share in the VM.
+pub type ContextNativeFn = fn(&mut NativeContext<'_>, &[Value]) -> Result<Value, String>;
+
+/// A native function of either kind.
+#[derive(Clone, Copy)]
+pub enum Native {
+    Plain(NativeFn),
+    WithContext(ContextNativeFn),
+}
+
+impl Native {
+    pub fn call(self, context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+        match self {
+            Self::Plain(function) => function(args),
+            Self::WithContext(function) => function(context, args),
+        }
+    }
+
+    /// Address of the function, which natives are compared by.
+    pub(crate) fn addr(self) -> usize {
+        match self {
+            Self::Plain(function) => function as usize,
+            Self::WithContext(function) => function as usize,
+        }
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({:#x})", self.addr())
+    }
+}
+
+/// A compiled function. The top-level script is a function without a name.
+pub struct Function {
+    /// Number of parameters, not counting the rest parameter.
+    pub arity: u8,
+    /// Whether the function has a rest parameter, which receives a list of
+    /// the arguments past the other parameters.
+    pub variadic: bool,
+    pub chunk: Chunk,
+    pub name: Option<Rc<str>>,
+    /// The imported module whose globals the function uses, set when the
+    /// module is imported. Functions of the main script leave it unset.
+    pub(crate) module: OnceCell<Rc<Module>>,
+}
+
+impl Function {
+    pub fn new(name: Option<Rc<str>>) -> Self {
+        Self { arity: 0, variadic: false, chunk: Chunk::new(), name, module: OnceCell::new() }
+    }
+
+    /// Name used in disassembly and stack traces.
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("script")
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => f.write_str("<script>"),
+        }
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
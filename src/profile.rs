@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LineStats {
+    pub count: u64,
+    pub time: Duration,
+}
+
+/// Per-line execution counts and accumulated time.
+///
+/// The time between two consecutive instructions is attributed to the
+/// line of the first one.
+#[derive(Debug, Default)]
+pub struct Profile {
+    lines: BTreeMap<usize, LineStats>,
+    last: Option<(usize, Instant)>,
+}
+
+const HEAT_WIDTH: usize = 10;
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&mut self, line: usize) {
+        let now = Instant::now();
+        self.finish_at(now);
+
+        self.lines.entry(line).or_default().count += 1;
+        self.last = Some((line, now));
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.finish_at(Instant::now());
+    }
+
+    fn finish_at(&mut self, now: Instant) {
+        if let Some((line, start)) = self.last.take() {
+            self.lines.entry(line).or_default().time += now - start;
+        }
+    }
+
+    pub fn line(&self, line: usize) -> Option<&LineStats> {
+        self.lines.get(&line)
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &LineStats)> {
+        self.lines.iter().map(|(line, stats)| (*line, stats))
+    }
+
+    /// Writes a copy of `source` with each line prefixed by its execution
+    /// count, accumulated time and a bar proportional to its share of the
+    /// total time.
+    pub fn write_annotated<W: Write>(&self, out: &mut W, source: &str) -> fmt::Result {
+        let total: Duration = self.lines.values().map(|stats| stats.time).sum();
+
+        writeln!(out, "{:>8} {:>12} {:<HEAT_WIDTH$} {:>4} |", "count", "time", "heat", "line")?;
+
+        for (idx, text) in source.lines().enumerate() {
+            let line = idx + 1;
+
+            match self.lines.get(&line) {
+                Some(stats) => {
+                    let share = if total.is_zero() {
+                        0.0
+                    }
+                    else {
+                        stats.time.as_secs_f64() / total.as_secs_f64()
+                    };
+                    let heat = "#".repeat((share * HEAT_WIDTH as f64).ceil() as usize);
+
+                    writeln!(
+                        out, "{:>8} {:>10.1}us {:<HEAT_WIDTH$} {:>4} | {}",
+                        stats.count, stats.time.as_secs_f64() * 1e6, heat, line, text
+                    )?;
+                },
+                None => {
+                    writeln!(out, "{:>8} {:>12} {:<HEAT_WIDTH$} {:>4} | {}", "", "", "", line, text)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn annotated_to_string(&self, source: &str) -> String {
+        let mut out = String::new();
+        self.write_annotated(&mut out, source).unwrap();
+        out
+    }
+}
@@ -0,0 +1,225 @@
+//! Recordings of the inputs a script read from outside the VM, such as the
+//! clock, standard input or files, so that a run can be replayed exactly.
+//!
+//! A [`VM`](crate::VM) records once started with
+//! [`VM::start_recording`](crate::VM::start_recording). Replaying a
+//! recording with [`VM::replay`](crate::VM::replay) seeds the random number
+//! generator as it was and hands the natives that read inputs the recorded
+//! results instead of reading them again. Natives with other effects, such
+//! as `writeFile` or `print`, run as usual.
+//!
+//! Recordings are encoded little endian:
+//!
+//! ```text
+//! "LOXR" version:u8 seed:u64 input_count:u32 (name value)*
+//!     name: length:u32 utf8_bytes
+//!     value: tag:u8 payload
+//!     payload: nil: none, bool: u8, number: f64, int: i64,
+//!              string: length:u32 utf8_bytes, list: count:u32 value*,
+//!              error: length:u32 utf8_bytes
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::value::Value;
+
+const MAGIC: &[u8; 4] = b"LOXR";
+
+/// Bumped whenever the layout changes.
+pub const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordingError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidTag(u8),
+    InvalidString,
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("Not a Lox recording"),
+            Self::UnsupportedVersion(version) => write!(
+                f, "Unsupported recording version {} (expected {})", version, FORMAT_VERSION
+            ),
+            Self::UnexpectedEnd => f.write_str("Unexpected end of recording"),
+            Self::TrailingBytes => f.write_str("Trailing bytes after recording"),
+            Self::InvalidTag(tag) => write!(f, "Invalid value tag {}", tag),
+            Self::InvalidString => f.write_str("String is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+/// What a native read from outside the VM.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Input {
+    /// Name of the native that read it, to catch replaying a recording of
+    /// a different script.
+    pub(crate) native: String,
+    /// Reading an input may fail too, such as a missing file.
+    pub(crate) result: Result<Value, String>,
+}
+
+/// The inputs of a run, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    /// State of the random number generator when recording started.
+    pub(crate) seed: u64,
+    pub(crate) inputs: Vec<Input>,
+}
+
+impl Recording {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { seed, inputs: Vec::new() }
+    }
+
+    /// Number of inputs recorded.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Panics if an input holds a value other than nil, a boolean, a number,
+    /// a string or a list of those, which the natives never read.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            encode_string(&mut bytes, &input.native);
+            match &input.result {
+                Ok(value) => encode_value(&mut bytes, value),
+                Err(message) => {
+                    bytes.push(6);
+                    encode_string(&mut bytes, message);
+                },
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, RecordingError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(RecordingError::BadMagic);
+        }
+        let version = reader.byte()?;
+        if version != FORMAT_VERSION {
+            return Err(RecordingError::UnsupportedVersion(version));
+        }
+
+        let seed = u64::from_le_bytes(reader.array()?);
+        let mut inputs = Vec::new();
+        for _ in 0..reader.u32()? {
+            let native = reader.string()?.to_owned();
+            let result = match reader.byte()? {
+                6 => Err(reader.string()?.to_owned()),
+                tag => Ok(decode_value(&mut reader, tag)?),
+            };
+            inputs.push(Input { native, result });
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(RecordingError::TrailingBytes);
+        }
+        Ok(Self { seed, inputs })
+    }
+}
+
+fn encode_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn encode_value(bytes: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => bytes.push(0),
+        Value::Bool(val) => bytes.extend_from_slice(&[1, *val as u8]),
+        Value::Number(val) => {
+            bytes.push(2);
+            bytes.extend_from_slice(&val.to_le_bytes());
+        },
+        Value::Int(val) => {
+            bytes.push(3);
+            bytes.extend_from_slice(&val.to_le_bytes());
+        },
+        Value::String(val) => {
+            bytes.push(4);
+            encode_string(bytes, val);
+        },
+        Value::List(list) => {
+            let list = list.borrow();
+            bytes.push(5);
+            bytes.extend_from_slice(&(list.len() as u32).to_le_bytes());
+            for value in list.iter() {
+                encode_value(bytes, value);
+            }
+        },
+        value => panic!("Inputs can't hold a {}", value.type_name()),
+    }
+}
+
+fn decode_value(reader: &mut Reader, tag: u8) -> Result<Value, RecordingError> {
+    let value = match tag {
+        0 => Value::Nil,
+        1 => Value::Bool(reader.byte()? != 0),
+        2 => Value::Number(f64::from_le_bytes(reader.array()?)),
+        3 => Value::Int(i64::from_le_bytes(reader.array()?)),
+        4 => Value::String(reader.string()?.into()),
+        5 => {
+            let mut list = Vec::new();
+            for _ in 0..reader.u32()? {
+                let tag = reader.byte()?;
+                list.push(decode_value(reader, tag)?);
+            }
+            Value::List(Rc::new(RefCell::new(list)))
+        },
+        tag => return Err(RecordingError::InvalidTag(tag)),
+    };
+    Ok(value)
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, count: usize) -> Result<&'b [u8], RecordingError> {
+        if self.bytes.len() < count {
+            return Err(RecordingError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], RecordingError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, RecordingError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, RecordingError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<&'b str, RecordingError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| RecordingError::InvalidString)
+    }
+}
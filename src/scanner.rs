@@ -1,12 +1,15 @@
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TokenType {
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Colon, Comma, Dot, DotDot, DotDotDot, Minus, Plus, Semicolon, Slash, Star, Percent,
+    Ampersand, Pipe, Caret, Tilde,
     Bang, BangEqual, Equal, EqualEqual,
-    Greater, GreaterEqual, Less, LessEqual,
-    Identifier, String, Number,
+    Greater, GreaterEqual, GreaterGreater, Less, LessEqual, LessLess,
+    Identifier, String, RawString, Number,
     And, Class, Else, False, For, Fun, If, Nil, Or, Print,
     Return, Super, This, True, Var, While,
+    // Only keywords with extensions
+    Break, Catch, Continue, Do, Import, In, Throw, Try,
 }
 
 type TT = TokenType;
@@ -27,23 +30,56 @@ impl<'s> Token<'s> {
     }
 }
 
+#[derive(Clone)]
 pub struct Scanner<'s> {
     source: &'s str,
     pub line: usize,
+    /// Whether lexical extensions such as block comments are recognized.
+    extensions: bool,
 }
 
 type ScanResult<'s> = Result<Option<Token<'s>>, &'static str>;
 
+/// Length of the digits at the start of `text`. With `separators`, the
+/// digits after the first may be interleaved with `_`.
+fn digit_run(text: &str, separators: bool) -> usize {
+    text.char_indices()
+        .find(|&(pos, c)| !(c.is_ascii_digit() || separators && pos > 0 && c == '_'))
+        .map_or(text.len(), |(pos, _)| pos)
+}
+
+/// Length of the exponent (e.g. `e-3`) at the start of `rest`, or 0 if
+/// there is none.
+fn exponent_length(rest: &str) -> usize {
+    let Some(exponent) = rest.strip_prefix(['e', 'E']) else {
+        return 0;
+    };
+    let sign = usize::from(exponent.starts_with(['+', '-']));
+    let digits = digit_run(&exponent[sign..], true);
+
+    if digits == 0 {
+        0
+    }
+    else {
+        1 + sign + digits
+    }
+}
+
 impl<'s> Scanner<'s> {
     pub fn new(source: &'s str) -> Self {
+        Self::with_extensions(source, true)
+    }
+
+    pub fn with_extensions(source: &'s str, extensions: bool) -> Self {
         Self {
             source,
             line: 1,
+            extensions,
         }
     }
 
     pub fn scan_token(&mut self) -> ScanResult<'s> {
-        self.skip_whitespace();
+        self.skip_whitespace()?;
 
         if self.source.is_empty() {
             return Ok(None);
@@ -54,6 +90,7 @@ impl<'s> Scanner<'s> {
 
         let tok = match ch {
             '"' => return self.string(),
+            'r' if next_ch == '"' => return self.raw_string(),
             '0'..='9' => return self.number(),
             'A'..='Z' | 'a'..='z' | '_' => return self.identifier(),
 
@@ -61,25 +98,37 @@ impl<'s> Scanner<'s> {
             '=' if next_ch == '=' => self.make_token(TT::EqualEqual, 2),
             '<' if next_ch == '=' => self.make_token(TT::LessEqual, 2),
             '>' if next_ch == '=' => self.make_token(TT::GreaterEqual, 2),
+            '<' if next_ch == '<' => self.make_token(TT::LessLess, 2),
+            '>' if next_ch == '>' => self.make_token(TT::GreaterGreater, 2),
 
             '(' => self.make_token(TT::LeftParen, 1),
             ')' => self.make_token(TT::RightParen, 1),
             '{' => self.make_token(TT::LeftBrace, 1),
             '}' => self.make_token(TT::RightBrace, 1),
+            '[' => self.make_token(TT::LeftBracket, 1),
+            ']' => self.make_token(TT::RightBracket, 1),
             ';' => self.make_token(TT::Semicolon, 1),
+            ':' => self.make_token(TT::Colon, 1),
             ',' => self.make_token(TT::Comma, 1),
+            '.' if self.source.starts_with("...") => self.make_token(TT::DotDotDot, 3),
+            '.' if next_ch == '.' => self.make_token(TT::DotDot, 2),
             '.' => self.make_token(TT::Dot, 1),
             '-' => self.make_token(TT::Minus, 1),
             '+' => self.make_token(TT::Plus, 1),
             '/' => self.make_token(TT::Slash, 1),
             '*' => self.make_token(TT::Star, 1),
+            '%' => self.make_token(TT::Percent, 1),
+            '&' => self.make_token(TT::Ampersand, 1),
+            '|' => self.make_token(TT::Pipe, 1),
+            '^' => self.make_token(TT::Caret, 1),
+            '~' => self.make_token(TT::Tilde, 1),
             '!' => self.make_token(TT::Bang, 1),
             '=' => self.make_token(TT::Equal, 1),
             '<' => self.make_token(TT::Less, 1),
             '>' => self.make_token(TT::Greater, 1),
 
             _ => {
-                self.advance(1);
+                self.advance(ch.len_utf8());
                 return Err("Unexpected character");
             }
         };
@@ -93,20 +142,18 @@ impl<'s> Scanner<'s> {
         Token::new(token_type, span, self.line)
     }
 
-    fn skip_whitespace(&mut self) {
+    fn skip_whitespace(&mut self) -> Result<(), &'static str> {
         loop {
             self.source = self.source
                 .trim_start_matches(|c: char| c.is_whitespace() && c != '\n');
 
             match self.source.chars().next() {
-                Some('/') => {
-                    if self.source.chars().nth(1).is_some_and(|c| c == '/') {
-                        self.source = self.source
-                            .trim_start_matches(|c: char| c != '\n')
-                    }
-                    else {
-                        break;
-                    }
+                Some('/') if self.source.chars().nth(1).is_some_and(|c| c == '/') => {
+                    self.source = self.source
+                        .trim_start_matches(|c: char| c != '\n')
+                },
+                Some('/') if self.extensions && self.source[1..].starts_with('*') => {
+                    self.block_comment()?;
                 },
                 Some('\n') => {
                     self.line += 1;
@@ -115,49 +162,122 @@ impl<'s> Scanner<'s> {
                 _ => { break; },
             }
         }
+        Ok(())
+    }
+
+    /// Skips a `/* ... */` comment, including any comments nested in it.
+    /// One that is never closed takes the rest of the source with it.
+    fn block_comment(&mut self) -> Result<(), &'static str> {
+        let bytes = self.source.as_bytes();
+        let mut depth = 0;
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            match &bytes[pos..] {
+                [b'/', b'*', ..] => {
+                    depth += 1;
+                    pos += 2;
+                },
+                [b'*', b'/', ..] => {
+                    depth -= 1;
+                    pos += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                [b'\n', ..] => {
+                    self.line += 1;
+                    pos += 1;
+                },
+                _ => pos += 1,
+            }
+        }
+
+        self.source = &self.source[pos..];
+        if depth > 0 {
+            return Err("Unterminated block comment");
+        }
+        Ok(())
     }
 
     fn string(&mut self) -> ScanResult<'s> {
-        for (pos, ch) in self.source.char_indices().skip(1) {
+        self.quoted(1, TT::String)
+    }
+
+    /// `r"..."`, whose contents are never processed for escapes.
+    fn raw_string(&mut self) -> ScanResult<'s> {
+        self.quoted(2, TT::RawString)
+    }
+
+    /// Scans up to the closing quote of a string whose opening delimiter is
+    /// `prefix_len` bytes long. With extensions, a backslash in a regular
+    /// string escapes the character after it, so `\"` doesn't close it.
+    fn quoted(&mut self, prefix_len: usize, token_type: TokenType) -> ScanResult<'s> {
+        let escapes = self.extensions && token_type == TT::String;
+        let mut escaped = false;
+
+        for (pos, ch) in self.source.char_indices().skip(prefix_len) {
             if ch == '\n' {
                 self.line += 1;
             }
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            if ch == '\\' && escapes {
+                escaped = true;
+                continue;
+            }
             if ch == '"' {
-                let tok = self.make_token(TT::String, pos + ch.len_utf8());
+                let tok = self.make_token(token_type, pos + ch.len_utf8());
 
                 return Ok(Some(tok));
             }
         }
 
-        self.advance(1);
+        self.advance(prefix_len);
         Err("Unterminated string")
     }
     
     fn number(&mut self) -> ScanResult<'s> {
-        let mut source_iter = self.source.char_indices();
-
-        let end = source_iter.by_ref()
-            .skip_while(|c| c.1.is_ascii_digit())
-            .next();
-
-        let next_num = source_iter.next()
-            .is_some_and(|c| c.1.is_ascii_digit());
-
-        let length = match end {
-            Some((_, '.')) if next_num => {
-                let end = source_iter
-                    .skip_while(|c| c.1.is_ascii_digit()).next();
-
-                if let Some((pos, _)) = end {
-                    pos
-                }
-                else {
-                    self.source.len()
-                }
-            },
-            Some((pos, _)) => pos,
-            None => self.source.len(),
-        };
+        if self.extensions {
+            match self.source.get(..2) {
+                Some("0x") => return self.radix_number(16, "Malformed hexadecimal number"),
+                Some("0b") => return self.radix_number(2, "Malformed binary number"),
+                _ => {},
+            }
+        }
+
+        let separators = self.extensions;
+        let mut length = digit_run(self.source, separators);
+
+        let rest = &self.source[length..];
+        if rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            length += 1 + digit_run(&rest[1..], separators);
+        }
+
+        if self.extensions {
+            length += exponent_length(&self.source[length..]);
+        }
+
+        Ok(Some(self.make_token(TT::Number, length)))
+    }
+
+    /// A number with a two character prefix such as `0x`. Every letter,
+    /// digit and separator after it is part of the literal, so `0x1G` is an error rather
+    /// than a number followed by an identifier.
+    fn radix_number(&mut self, radix: u32, malformed: &'static str) -> ScanResult<'s> {
+        let length = self.source[2..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(self.source.len(), |pos| pos + 2);
+
+        let digits = &self.source[2..length];
+        let valid = digits.chars().all(|c| c.is_digit(radix) || c == '_')
+            && digits.chars().any(|c| c.is_digit(radix));
+        if !valid {
+            self.advance(length);
+            return Err(malformed);
+        }
 
         Ok(Some(self.make_token(TT::Number, length)))
     }
@@ -165,10 +285,8 @@ impl<'s> Scanner<'s> {
     fn identifier(&mut self) -> ScanResult<'s> {
         let pos = self.source.char_indices()
             .skip(1)
-            .skip_while(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
-            .next()
-            .unwrap()
-            .0;
+            .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+            .map_or(self.source.len(), |(pos, _)| pos);
 
         let span = &self.source[..pos];
         let token_type = self.identifier_type(span);
@@ -182,7 +300,11 @@ impl<'s> Scanner<'s> {
 
         match (chars.next().unwrap_or_default(), chars.as_str()) {
             ('a', "nd") => TT::And,
+            ('b', "reak") if self.extensions => TT::Break,
             ('c', "lass") => TT::Class,
+            ('c', "atch") if self.extensions => TT::Catch,
+            ('c', "ontinue") if self.extensions => TT::Continue,
+            ('d', "o") if self.extensions => TT::Do,
             ('e', "lse") => TT::Else,
             ('f', _) => match (chars.next().unwrap_or_default(), chars.as_str()) {
                 ('a', "lse") => TT::False,
@@ -191,6 +313,8 @@ impl<'s> Scanner<'s> {
                 _ => TT::Identifier,
             }
             ('i', "f") => TT::If,
+            ('i', "mport") if self.extensions => TT::Import,
+            ('i', "n") if self.extensions => TT::In,
             ('n', "il") => TT::Nil,
             ('o', "r") => TT::Or,
             ('p', "rint") => TT::Print,
@@ -198,7 +322,9 @@ impl<'s> Scanner<'s> {
             ('s', "uper") => TT::Super,
             ('t', _) => match (chars.next().unwrap_or_default(), chars.as_str()) {
                 ('h', "is") => TT::This,
+                ('h', "row") if self.extensions => TT::Throw,
                 ('r', "ue") => TT::True,
+                ('r', "y") if self.extensions => TT::Try,
                 _ => TT::Identifier,
             }
             ('v', "ar") => TT::Var,
@@ -1,81 +1,692 @@
-use std::cmp;
-use std::fmt::Display;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
 use std::ops;
+use std::rc::Rc;
 
-#[derive(Clone, Debug, PartialEq)]
+use indexmap::IndexMap;
+
+use crate::object::{Function, Native};
+
+/// A Lox value.
+///
+/// Numbers have two representations: `Int` holds integer literals and the
+/// results of integer arithmetic, and is promoted to a `Number` on overflow,
+/// inexact division, or when mixed with a `Number`. Both are the same type
+/// as far as scripts are concerned.
+///
+/// Strings and functions are immutable and shared, so cloning a value never
+/// copies one. Lists and maps are shared too, so changes made through one
+/// value are seen through all of its clones. Maps keep their entries in
+/// insertion order. Ranges hold the integers from `start` up to but not
+/// including `end`.
+#[derive(Clone, Debug)]
 pub enum Value {
     Nil,
     Bool(bool),
     Number(f64),
+    Int(i64),
+    String(Rc<str>),
+    Function(Rc<Function>),
+    NativeFn(Native),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<IndexMap<Value, Value>>>),
+    Range { start: i64, end: i64 },
+}
+
+/// An operation was applied to values of the wrong type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    Unary { operator: &'static str, operand: Value },
+    Binary { operator: &'static str, lhs: Value, rhs: Value },
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unary { operator, operand } => write!(
+                f, "Operand to '{}' must be a number; got {} ({}).",
+                operator, operand.type_name(), operand
+            ),
+            Self::Binary { operator, lhs, rhs } => write!(
+                f, "Operands to '{}' must be {}; got {} ({}) and {} ({}).",
+                operator,
+                match *operator {
+                    "+" => "two numbers or two strings",
+                    ".." => "two integers",
+                    _ => "two numbers",
+                },
+                lhs.type_name(), lhs, rhs.type_name(), rhs
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// An arithmetic result rejected by strict numeric mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NumericError {
+    NotFinite { operator: &'static str, result: f64 },
+    PrecisionLoss { operator: &'static str },
+}
+
+impl Display for NumericError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFinite { operator, result } => write!(
+                f, "Result of '{}' is not a finite number ({}).", operator, result
+            ),
+            Self::PrecisionLoss { operator } => write!(
+                f, "Result of '{}' cannot be represented exactly.", operator
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NumericError {}
+
+/// An index expression failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexError {
+    /// The indexed value is not a list, map or string, or it is a string
+    /// being assigned to.
+    NotIndexable { assign: bool, target: Value },
+    NotSliceable(Value),
+    NotAnInteger(Value),
+    OutOfRange { index: Value, len: usize },
+    MissingKey(Value),
+    /// A value other than a list with `expected` elements was unpacked.
+    Unpack { expected: usize, value: Value },
+    /// A value other than a map was unpacked by key.
+    UnpackMap(Value),
+    NotIterable(Value),
 }
 
+impl Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotIndexable { assign: false, target } => write!(
+                f, "Can only index lists, maps and strings; got {} ({}).",
+                target.type_name(), target
+            ),
+            Self::NotIndexable { assign: true, target } => write!(
+                f, "Can only assign to elements of lists and maps; got {} ({}).",
+                target.type_name(), target
+            ),
+            Self::NotSliceable(target) => write!(
+                f, "Can only slice lists and strings; got {} ({}).",
+                target.type_name(), target
+            ),
+            Self::NotAnInteger(index) => write!(
+                f, "Index must be an integer; got {} ({}).", index.type_name(), index
+            ),
+            Self::OutOfRange { index, len } => write!(
+                f, "Index {} out of range for length {}.", index, len
+            ),
+            Self::Unpack { expected, value: Value::List(list) } => write!(
+                f, "Expected {} values to unpack but got {}.", expected, list.borrow().len()
+            ),
+            Self::Unpack { value, .. } => write!(
+                f, "Can only unpack lists; got {} ({}).", value.type_name(), value
+            ),
+            Self::UnpackMap(value) => write!(
+                f, "Can only unpack maps by key; got {} ({}).", value.type_name(), value
+            ),
+            Self::NotIterable(value) => write!(
+                f, "Can only iterate over lists, maps, strings and ranges; got {} ({}).",
+                value.type_name(), value
+            ),
+            Self::MissingKey(key) => {
+                f.write_str("Key ")?;
+                fmt_element(key, f)?;
+                f.write_str(" not found.")
+            },
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
+
+/// Largest magnitude up to which every integer is exactly representable
+/// as an f64.
+const MAX_EXACT_INT: u64 = 1 << 53;
+
+/// Validates the result of an arithmetic operation for strict numeric mode,
+/// rejecting infinities and NaN produced from finite operands, and integer
+/// results that had to be rounded into a float.
+pub(crate) fn check_strict(
+    operator: &'static str, operands: &[Value], result: &Value
+) -> Result<(), NumericError> {
+    let Value::Number(result) = *result else {
+        return Ok(());
+    };
+
+    let finite_operands = operands.iter()
+        .all(|val| val.as_f64().is_some_and(f64::is_finite));
+    if !result.is_finite() && finite_operands {
+        return Err(NumericError::NotFinite { operator, result });
+    }
+
+    let inexact_operand = operands.iter()
+        .any(|val| matches!(val, Value::Int(i) if i.unsigned_abs() > MAX_EXACT_INT));
+    // Integer operations only produce floats when they overflow, except for
+    // division which also does so for fractional results.
+    let overflowed = operator != "/"
+        && operands.iter().all(|val| matches!(val, Value::Int(_)));
+
+    if inexact_operand || overflowed {
+        return Err(NumericError::PrecisionLoss { operator });
+    }
+    Ok(())
+}
+
+type ValueResult<T = Value> = Result<T, TypeError>;
+
 impl Value {
     pub fn is_falsey(&self) -> bool {
         matches!(self, Self::Nil | Self::Bool(false))
     }
-}
 
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Self::Nil => f.write_str("nil"),
-            Self::Bool(val) => write!(f, "{}", val),
-            Self::Number(val) => write!(f, "{}", val),
+            Self::Nil => "nil",
+            Self::Bool(_) => "bool",
+            Self::Number(_) | Self::Int(_) => "number",
+            Self::String(_) => "string",
+            Self::Function(_) | Self::NativeFn(_) => "function",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Range { .. } => "range",
         }
     }
-}
 
-impl ops::Neg for Value {
-    type Output = Self;
+    /// The numeric value as a float, if this is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(val) => Some(*val),
+            Self::Int(val) => Some(*val as f64),
+            _ => None,
+        }
+    }
+
+    pub fn checked_neg(self) -> ValueResult {
+        match self {
+            Self::Number(val) => Ok(Self::Number(-val)),
+            Self::Int(val) => Ok(val.checked_neg()
+                .map_or(Self::Number(-(val as f64)), Self::Int)),
+            operand => Err(TypeError::Unary { operator: "-", operand }),
+        }
+    }
+
+    pub fn checked_add(self, rhs: Self) -> ValueResult {
+        if let (Self::String(a), Self::String(b)) = (&self, &rhs) {
+            let mut concatenated = String::with_capacity(a.len() + b.len());
+            concatenated.push_str(a);
+            concatenated.push_str(b);
+            return Ok(Self::String(concatenated.into()));
+        }
+        Self::arithmetic("+", self, rhs, i64::checked_add, |a, b| a + b)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> ValueResult {
+        Self::arithmetic("-", self, rhs, i64::checked_sub, |a, b| a - b)
+    }
+
+    pub fn checked_mul(self, rhs: Self) -> ValueResult {
+        Self::arithmetic("*", self, rhs, i64::checked_mul, |a, b| a * b)
+    }
+
+    pub fn checked_div(self, rhs: Self) -> ValueResult {
+        let exact_div = |a: i64, b: i64| {
+            a.checked_rem(b)
+                .filter(|rem| *rem == 0)
+                .and_then(|_| a.checked_div(b))
+        };
+        Self::arithmetic("/", self, rhs, exact_div, |a, b| a / b)
+    }
+
+    /// Remainder of truncating division, with the sign of the dividend.
+    pub fn checked_rem(self, rhs: Self) -> ValueResult {
+        Self::arithmetic("%", self, rhs, i64::checked_rem, |a, b| a % b)
+    }
+
+    // Bitwise operations work on the number truncated to an i64, so they
+    // always produce an integer.
+
+    pub fn bit_and(self, rhs: Self) -> ValueResult {
+        Self::bitwise("&", self, rhs, |a, b| a & b)
+    }
+
+    pub fn bit_or(self, rhs: Self) -> ValueResult {
+        Self::bitwise("|", self, rhs, |a, b| a | b)
+    }
+
+    pub fn bit_xor(self, rhs: Self) -> ValueResult {
+        Self::bitwise("^", self, rhs, |a, b| a ^ b)
+    }
+
+    /// Shifting by 64 or more bits, or by a negative amount, shifts every
+    /// bit out.
+    pub fn shift_left(self, rhs: Self) -> ValueResult {
+        Self::bitwise("<<", self, rhs, |val, amount| {
+            val.checked_shl(shift_amount(amount)).unwrap_or(0)
+        })
+    }
+
+    /// Arithmetic shift, keeping the sign of the left operand. Shifting by
+    /// 64 or more bits, or by a negative amount, leaves only the sign.
+    pub fn shift_right(self, rhs: Self) -> ValueResult {
+        Self::bitwise(">>", self, rhs, |val, amount| val >> shift_amount(amount).min(63))
+    }
+
+    pub fn bit_not(self) -> ValueResult {
+        match self.as_bits() {
+            Some(val) => Ok(Self::Int(!val)),
+            None => Err(TypeError::Unary { operator: "~", operand: self }),
+        }
+    }
+
+    /// The element at `index` of a list or string, or the value for the key
+    /// `index` of a map. Negative indices count from the end.
+    pub fn get_index(&self, index: &Self) -> Result<Self, IndexError> {
+        match self {
+            Self::List(list) => {
+                let list = list.borrow();
+                let position = element_index(index, list.len())?;
+                Ok(list[position].clone())
+            },
+            Self::String(string) => {
+                let position = element_index(index, string.chars().count())?;
+                let ch = string.chars().nth(position).unwrap();
+                Ok(Self::String(ch.to_string().into()))
+            },
+            Self::Map(map) => map.borrow().get(index)
+                .cloned()
+                .ok_or_else(|| IndexError::MissingKey(index.clone())),
+            _ => Err(IndexError::NotIndexable { assign: false, target: self.clone() }),
+        }
+    }
 
-    fn neg(self) -> Self::Output {
+    /// Replaces the element at `index` of a list, or sets the value for the
+    /// key `index` of a map, adding the key if it is missing.
+    pub fn set_index(&self, index: Self, value: Self) -> Result<(), IndexError> {
         match self {
-            Self::Number(val) => Self::Number(-val),
-            _ => panic!("Cannot negate non-number Value"),
+            Self::List(list) => {
+                let mut list = list.borrow_mut();
+                let position = element_index(&index, list.len())?;
+                list[position] = value;
+            },
+            Self::Map(map) => {
+                map.borrow_mut().insert(index, value);
+            },
+            _ => return Err(IndexError::NotIndexable { assign: true, target: self.clone() }),
         }
+        Ok(())
+    }
+
+    /// The elements of a list with exactly `count` of them.
+    pub fn unpack(self, count: usize) -> Result<Vec<Self>, IndexError> {
+        match &self {
+            Self::List(list) if list.borrow().len() == count => Ok(list.borrow().clone()),
+            _ => Err(IndexError::Unpack { expected: count, value: self }),
+        }
+    }
+
+    /// The values for `keys` in a map, which must have all of them.
+    pub fn unpack_keys(self, keys: &[Self]) -> Result<Vec<Self>, IndexError> {
+        let Self::Map(map) = &self else {
+            return Err(IndexError::UnpackMap(self));
+        };
+        let map = map.borrow();
+        keys.iter()
+            .map(|key| map.get(key).cloned().ok_or_else(|| IndexError::MissingKey(key.clone())))
+            .collect()
+    }
+
+    /// A new list or string with the elements of this one from `start` up to
+    /// but not including `end`. Negative bounds count from the end and
+    /// bounds past either end are clamped, while a `nil` bound stands for
+    /// the start or the end.
+    pub fn slice(&self, start: &Self, end: &Self) -> Result<Self, IndexError> {
+        match self {
+            Self::List(list) => {
+                let list = list.borrow();
+                let range = slice_range(start, end, list.len())?;
+                Ok(Self::List(Rc::new(RefCell::new(list[range].to_vec()))))
+            },
+            Self::String(string) => {
+                let range = slice_range(start, end, string.chars().count())?;
+                let sliced: String = string.chars()
+                    .skip(range.start)
+                    .take(range.len())
+                    .collect();
+                Ok(Self::String(sliced.into()))
+            },
+            _ => Err(IndexError::NotSliceable(self.clone())),
+        }
+    }
+
+    /// The range from this number up to but not including `end`. Both must
+    /// be integers.
+    pub fn range(self, end: Self) -> ValueResult {
+        match (as_integer(&self), as_integer(&end)) {
+            (Some(start), Some(end)) => Ok(Self::Range { start, end }),
+            _ => Err(TypeError::Binary { operator: "..", lhs: self, rhs: end }),
+        }
+    }
+
+    /// The next step of an iteration over the elements of a list, string or
+    /// range, or the keys of a map, as the element and the state to resume
+    /// from. The iteration starts from a `nil` state, and `None` means it
+    /// is done.
+    pub fn iterate(&self, state: &Self) -> Result<Option<(Self, Self)>, IndexError> {
+        // The state is a position past the start for everything but ranges
+        let position = match state {
+            Self::Int(position) => *position as usize,
+            _ => 0,
+        };
+        let step = match self {
+            Self::List(list) => list.borrow()
+                .get(position)
+                .map(|element| (element.clone(), position + 1)),
+            Self::Map(map) => map.borrow()
+                .get_index(position)
+                .map(|(key, _)| (key.clone(), position + 1)),
+            // Strings step by bytes, to avoid counting chars from the start
+            Self::String(string) => string[position..].chars()
+                .next()
+                .map(|ch| (Self::String(ch.to_string().into()), position + ch.len_utf8())),
+            Self::Range { start, end } => {
+                let current = match state {
+                    Self::Int(current) => *current,
+                    _ => *start,
+                };
+                let step = (current < *end).then(|| (Self::Int(current), Self::Int(current + 1)));
+                return Ok(step);
+            },
+            _ => return Err(IndexError::NotIterable(self.clone())),
+        };
+        Ok(step.map(|(element, position)| (element, Self::Int(position as i64))))
+    }
+
+    /// The number truncated towards zero, saturating at the bounds of i64.
+    /// NaN becomes 0.
+    fn as_bits(&self) -> Option<i64> {
+        match self {
+            Self::Int(val) => Some(*val),
+            Self::Number(val) => Some(*val as i64),
+            _ => None,
+        }
+    }
+
+    fn bitwise(operator: &'static str, lhs: Self, rhs: Self, op: fn(i64, i64) -> i64) -> ValueResult {
+        match (lhs.as_bits(), rhs.as_bits()) {
+            (Some(a), Some(b)) => Ok(Self::Int(op(a, b))),
+            _ => Err(TypeError::Binary { operator, lhs, rhs }),
+        }
+    }
+
+    // Numbers follow IEEE 754: NaN is unordered, so every comparison
+    // involving it is false. The VM relies on this by having dedicated
+    // opcodes for `>=` and `<=` instead of negating `<` and `>`.
+
+    pub fn greater(&self, rhs: &Self) -> ValueResult<bool> {
+        Self::compare(">", self, rhs, |ord| ord == Ordering::Greater)
+    }
+
+    pub fn greater_equal(&self, rhs: &Self) -> ValueResult<bool> {
+        Self::compare(">=", self, rhs, |ord| ord != Ordering::Less)
+    }
+
+    pub fn less(&self, rhs: &Self) -> ValueResult<bool> {
+        Self::compare("<", self, rhs, |ord| ord == Ordering::Less)
+    }
+
+    pub fn less_equal(&self, rhs: &Self) -> ValueResult<bool> {
+        Self::compare("<=", self, rhs, |ord| ord != Ordering::Greater)
+    }
+
+    /// Applies `int_op` when both operands are integers, falling back to
+    /// `float_op` when either is a float or `int_op` can't give an exact
+    /// result.
+    fn arithmetic(
+        operator: &'static str, lhs: Self, rhs: Self,
+        int_op: fn(i64, i64) -> Option<i64>, float_op: fn(f64, f64) -> f64
+    ) -> ValueResult {
+        if let (Self::Int(a), Self::Int(b)) = (&lhs, &rhs) {
+            if let Some(result) = int_op(*a, *b) {
+                return Ok(Self::Int(result));
+            }
+        }
+
+        match (lhs.as_f64(), rhs.as_f64()) {
+            (Some(a), Some(b)) => Ok(Self::Number(float_op(a, b))),
+            _ => Err(TypeError::Binary { operator, lhs, rhs }),
+        }
+    }
+
+    fn compare(
+        operator: &'static str, lhs: &Self, rhs: &Self, test: fn(Ordering) -> bool
+    ) -> ValueResult<bool> {
+        let ordering = match (lhs, rhs) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(b)),
+            _ => match (lhs.as_f64(), rhs.as_f64()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => return Err(TypeError::Binary {
+                    operator, lhs: lhs.clone(), rhs: rhs.clone()
+                }),
+            },
+        };
+        Ok(ordering.is_some_and(test))
     }
 }
 
-impl ops::Add for Value {
-    type Output = Self;
+/// The value of an integral number.
+pub(crate) fn as_integer(value: &Value) -> Option<i64> {
+    match *value {
+        Value::Int(val) => Some(val),
+        Value::Number(val) if val.fract() == 0.0 => Some(val as i64),
+        _ => None,
+    }
+}
+
+/// Position in a sequence of `len` elements for an integral `index`, which
+/// counts from the end when negative.
+fn element_index(index: &Value, len: usize) -> Result<usize, IndexError> {
+    let position = relative_index(index, len)?;
+
+    usize::try_from(position).ok()
+        .filter(|position| *position < len)
+        .ok_or_else(|| IndexError::OutOfRange { index: index.clone(), len })
+}
 
-    fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Self::Number(lhs + rhs),
-            _ => panic!("Cannot add non-number Values")
+/// Range of a sequence of `len` elements selected by slice bounds.
+fn slice_range(start: &Value, end: &Value, len: usize) -> Result<ops::Range<usize>, IndexError> {
+    let clamp = |bound: &Value, default: usize| match bound {
+        Value::Nil => Ok(default),
+        _ => relative_index(bound, len).map(|position| position.clamp(0, len as i64) as usize),
+    };
+
+    let start = clamp(start, 0)?;
+    let end = clamp(end, len)?;
+    Ok(start..end.max(start))
+}
+
+/// An integral `index` into a sequence of `len` elements, made relative to
+/// the end when negative. The result may still be out of range.
+fn relative_index(index: &Value, len: usize) -> Result<i64, IndexError> {
+    let position = as_integer(index).ok_or_else(|| IndexError::NotAnInteger(index.clone()))?;
+
+    if position < 0 {
+        Ok(position.saturating_add(len as i64))
+    }
+    else {
+        Ok(position)
+    }
+}
+
+/// The number of bits to shift by. Negative amounts count as more bits
+/// than an i64 has.
+fn shift_amount(amount: i64) -> u32 {
+    u32::try_from(amount).unwrap_or(u32::MAX)
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => f.write_str("nil"),
+            Self::Bool(val) => write!(f, "{}", val),
+            Self::Number(val) => fmt_number(*val, f),
+            Self::Int(val) => write!(f, "{}", val),
+            Self::String(val) => f.write_str(val),
+            Self::Function(function) => function.fmt(f),
+            Self::NativeFn(_) => f.write_str("<native fn>"),
+            Self::List(list) => fmt_once(Rc::as_ptr(list).cast(), "[...]", f, |f| {
+                f.write_str("[")?;
+                for (index, element) in list.borrow().iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    fmt_element(element, f)?;
+                }
+                f.write_str("]")
+            }),
+            Self::Map(map) => fmt_once(Rc::as_ptr(map).cast(), "{...}", f, |f| {
+                f.write_str("{")?;
+                for (index, (key, value)) in map.borrow().iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    fmt_element(key, f)?;
+                    f.write_str(": ")?;
+                    fmt_element(value, f)?;
+                }
+                f.write_str("}")
+            }),
+            Self::Range { start, end } => write!(f, "{}..{}", start, end),
         }
     }
 }
 
-impl ops::Sub for Value {
-    type Output = Self;
+thread_local! {
+    /// Lists and maps being written, outermost first.
+    static WRITING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Self::Number(lhs - rhs),
-            _ => panic!("Cannot substract non-number Values")
+/// Writes a list or map with `write`, or `cycle` if it is already being
+/// written further out because it contains itself.
+fn fmt_once(
+    container: *const (), cycle: &str, f: &mut fmt::Formatter<'_>,
+    write: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result
+) -> fmt::Result {
+    let nested = WRITING.with_borrow_mut(|writing| {
+        let nested = writing.contains(&container);
+        if !nested {
+            writing.push(container);
         }
+        nested
+    });
+    if nested {
+        return f.write_str(cycle);
     }
+
+    let result = write(f);
+    WRITING.with_borrow_mut(Vec::pop);
+    result
 }
 
-impl ops::Mul for Value {
-    type Output = Self;
+/// Writes a value inside a list or map. Strings are quoted, so `["a, b"]`
+/// and `["a", "b"]` can be told apart.
+fn fmt_element(value: &Value, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match value {
+        Value::String(val) => write!(f, "{:?}", val),
+        _ => value.fmt(f),
+    }
+}
+
+/// Writes the shortest representation that parses back into exactly the
+/// same f64. Very large and very small magnitudes use exponent notation
+/// (`1e300`, `5e-324`) instead of hundreds of positional digits.
+fn fmt_number(val: f64, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let magnitude = val.abs();
+    if val.is_finite() && val != 0.0 && !(1e-7..1e21).contains(&magnitude) {
+        write!(f, "{:e}", val)
+    }
+    else {
+        write!(f, "{}", val)
+    }
+}
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Self::Number(lhs * rhs),
-            _ => panic!("Cannot multiply non-number Values")
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(a, b),
+            (Self::NativeFn(a), Self::NativeFn(b)) => a.addr() == b.addr(),
+            (Self::List(a), Self::List(b)) => Rc::ptr_eq(a, b),
+            (Self::Map(a), Self::Map(b)) => Rc::ptr_eq(a, b),
+            (Self::Range { start: a, end: b }, Self::Range { start: c, end: d }) => a == c && b == d,
+            // Compared exactly, so large integers don't equal nearby floats
+            (Self::Int(i), Self::Number(f)) | (Self::Number(f), Self::Int(i)) => {
+                f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64
+                    && *f as i64 == *i
+            },
+            _ => false,
         }
     }
 }
 
-impl ops::Div for Value {
-    type Output = Self;
+// Needed to use values as map keys. NaN is the one value not equal to
+// itself, so an entry keyed by NaN can't be looked up again.
+impl Eq for Value {}
 
-    fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Self::Number(lhs / rhs),
-            _ => panic!("Cannot divide non-number Values")
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Integral floats hash like the integer they equal
+        let int = match self {
+            Self::Int(val) => Some(*val),
+            Self::Number(val) => Some(*val)
+                .filter(|val| val.fract() == 0.0 && *val >= i64::MIN as f64 && *val < i64::MAX as f64)
+                .map(|val| val as i64),
+            _ => None,
+        };
+        if let Some(int) = int {
+            state.write_u8(0);
+            int.hash(state);
+            return;
+        }
+
+        match self {
+            Self::Nil => state.write_u8(1),
+            Self::Bool(val) => {
+                state.write_u8(2);
+                val.hash(state);
+            },
+            Self::Number(val) => {
+                state.write_u8(3);
+                val.to_bits().hash(state);
+            },
+            Self::String(val) => {
+                state.write_u8(4);
+                val.hash(state);
+            },
+            Self::Range { start, end } => {
+                state.write_u8(5);
+                start.hash(state);
+                end.hash(state);
+            },
+            // Compared by identity, so they hash by address
+            Self::Function(function) => Rc::as_ptr(function).hash(state),
+            Self::NativeFn(native) => native.addr().hash(state),
+            Self::List(list) => Rc::as_ptr(list).hash(state),
+            Self::Map(map) => Rc::as_ptr(map).hash(state),
+            Self::Int(_) => unreachable!(),
         }
     }
 }
@@ -87,12 +698,3 @@ impl ops::Not for Value {
         Self::Bool(self.is_falsey())
     }
 }
-
-impl cmp::PartialOrd for Value {
-    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match (self, other) {
-            (Self::Number(a), Self::Number(b)) => a.partial_cmp(b),
-            _ => panic!("Cannot compare non-number Values")
-        }
-    }
-}
\ No newline at end of file
@@ -1,166 +1,1416 @@
-use std::cmp;
-use std::ops;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use indexmap::IndexMap;
+
+use crate::cache::ChunkCache;
 use crate::chunk::{Chunk, OpCode};
-use crate::compiler::Compiler;
-use crate::value::Value;
-use crate::debug::disassemble_instruction;
+use crate::compiler::{Compiler, CompilerOptions};
+use crate::object::{ContextNativeFn, Function, Native, NativeFn};
+use crate::value::{self, IndexError, TypeError, Value};
+use crate::natives::{self, NativeContext, NativePackage, NativeState};
+use crate::debug::{disassemble_instruction, instruction_to_string, write_json_trace};
+use crate::profile::Profile;
+use crate::replay::Recording;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InterpretResult {
     CompileError,
     RuntimeError,
+    /// Execution was stopped through an [`InterruptHandle`].
+    Interrupted,
+    /// The script called `exit` with the code, which stops it like a
+    /// runtime error that can't be caught but reports nothing.
+    Exit(i32),
+    /// The VM reached an inconsistent state, e.g. a stack underflow caused
+    /// by bad bytecode.
+    InternalError,
 }
 
-pub struct VM {
-    chunk: Option<Chunk>,
+/// Stops a running VM from another thread or a signal handler.
+///
+/// The VM checks for interrupts before each instruction and stops with an
+/// "Interrupted." runtime report. Interrupts requested while the VM is not
+/// running are discarded when the next run starts.
+///
+/// ```
+/// use m2_rslox::{InterpretResult, VM};
+///
+/// let mut vm = VM::new();
+/// let interrupt = vm.interrupt_handle();
+/// vm.set_visualizer(Some(Box::new(move |_| interrupt.interrupt())));
+/// vm.set_error_output(Box::new(std::io::sink()));
+///
+/// assert_eq!(vm.interpret("print 1 + 2;"), Err(InterpretResult::Interrupted));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether an interrupt is pending, without handling it.
+    pub(crate) fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    Off,
+    Instructions,
+    Stack,
+}
+
+/// Controls the execution trace printed to stderr.
+///
+/// An instruction is traced only if it passes every filter that is set.
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    pub level: TraceLevel,
+    /// Only trace these opcodes, by name (e.g. "Add"). Empty traces all.
+    pub opcodes: Vec<String>,
+    /// Only trace instructions executed inside the named function.
+    pub function: Option<String>,
+    /// Skip the first `after` executed instructions.
+    pub after: usize,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        let level = if cfg!(feature = "debug_trace_execution") {
+            TraceLevel::Stack
+        }
+        else {
+            TraceLevel::Off
+        };
+
+        Self { level, opcodes: Vec::new(), function: None, after: 0 }
+    }
+}
+
+impl TraceOptions {
+    fn should_trace(&self, instruction: &OpCode, function: &str, executed: usize) -> bool {
+        self.level > TraceLevel::Off
+            && executed > self.after
+            && self.function.as_ref().is_none_or(|name| name == function)
+            && (self.opcodes.is_empty() || self.opcodes.iter().any(|op| op == instruction.name()))
+    }
+}
+
+/// Snapshot of the machine taken right after an instruction executes.
+pub struct MachineState<'a> {
+    /// Number of instructions executed so far, including this one.
+    pub step: usize,
+    /// Offset of the instruction that was executed.
+    pub offset: usize,
+    pub instruction: &'a OpCode,
+    pub line: usize,
+    /// Offset of the next instruction to execute.
+    pub ip: usize,
+    pub stack: &'a [Value],
+    /// Global defined or assigned by the instruction, with its new value.
+    pub global: Option<(&'a str, &'a Value)>,
+}
+
+impl fmt::Display for MachineState<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:5} {:04} {:4} {:<16} ip={:04} |",
+            self.step, self.offset, self.line, format!("{:?}", self.instruction), self.ip)?;
+        for value in self.stack {
+            write!(f, " [{}]", value)?;
+        }
+        if let Some((name, value)) = self.global {
+            write!(f, "  {} <- {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+pub type Visualizer = Box<dyn FnMut(&MachineState)>;
+
+/// Maximum depth of nested calls unless set with [`VM::set_frame_limit`].
+const DEFAULT_FRAME_LIMIT: usize = 64;
+
+/// A function invocation in progress.
+pub(crate) struct CallFrame {
+    pub(crate) function: Rc<Function>,
     ip: usize,
+    /// Stack index of the frame's slot 0, which holds the function itself.
+    slots: usize,
+}
+
+impl CallFrame {
+    /// Line of the instruction the frame last started, which for callers is
+    /// their `Call`.
+    pub(crate) fn line(&self) -> usize {
+        self.function.chunk.line_at(self.ip.saturating_sub(1))
+    }
+}
+
+/// A `try` block being executed.
+struct Handler {
+    /// Number of frames when the block started, so the innermost one is
+    /// the frame running it.
+    frames: usize,
+    stack_height: usize,
+    /// Offset of the handler code in that frame's chunk.
+    target: usize,
+}
+
+/// The globals of the main script or of an imported module.
+pub(crate) struct Module {
+    /// The file of an imported module, which its imports are resolved
+    /// relative to. `None` for the main script.
+    path: Option<PathBuf>,
+    globals: RefCell<HashMap<Rc<str>, Value>>,
+}
+
+impl Module {
+    fn new(path: Option<PathBuf>) -> Self {
+        Self { path, globals: RefCell::new(HashMap::new()) }
+    }
+}
+
+/// A module whose script is running because of an `Import`.
+struct Import {
+    module: Rc<Module>,
+    /// Number of frames once the module's script was called.
+    frames: usize,
+}
+
+pub struct VM {
+    frames: Vec<CallFrame>,
+    /// Modules being imported, innermost last.
+    imports: Vec<Import>,
+    /// Namespaces of the modules that ran, by canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// Natives that can be imported as `"std:name"`, by name.
+    native_modules: HashMap<&'static str, &'static [(&'static str, ContextNativeFn)]>,
+    /// Namespaces of the native modules imported so far.
+    native_namespaces: HashMap<&'static str, Value>,
+    /// Innermost last.
+    handlers: Vec<Handler>,
+    /// Value thrown towards the innermost handler, until it is caught.
+    thrown: Option<Value>,
+    /// Offset of the instruction being executed in the innermost frame.
+    /// Unlike `ip - 1`, this stays valid after an instruction moves `ip`.
+    current: usize,
     stack: Vec<Value>,
+    /// Globals defined by the host, such as the natives, which every module
+    /// sees unless it defines a global of the same name.
+    builtins: HashMap<Rc<str>, Value>,
+    main: Rc<Module>,
+    /// Path of the main script, which its imports are resolved relative to.
+    script_path: Option<PathBuf>,
+    natives: NativeState,
+    /// Names of the installed native packages.
+    packages: HashSet<String>,
+    json_trace: Option<Box<dyn io::Write>>,
+    trace: TraceOptions,
+    executed: usize,
+    profile: Option<Profile>,
+    visualizer: Option<Visualizer>,
+    strict_numbers: bool,
+    compiler_options: CompilerOptions,
+    chunk_cache: Option<ChunkCache>,
+    fuel: Option<usize>,
+    stack_size: Option<usize>,
+    frame_limit: usize,
+    interrupt: InterruptHandle,
+    output: Box<dyn io::Write>,
+    error_output: Box<dyn io::Write>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
+    /// A VM with the default configuration, the built-in natives, such as
+    /// `clock`, and the functions of the prelude. Use [`VM::builder`] to
+    /// configure one.
     pub fn new() -> Self {
-        Self { chunk: None, ip: 0, stack: Vec::new() }
+        let mut vm = Self::without_prelude();
+        vm.load_prelude();
+        vm
+    }
+
+    fn without_prelude() -> Self {
+        natives::start_clock();
+        let interrupt = InterruptHandle::default();
+        let mut vm = Self {
+            frames: Vec::new(),
+            imports: Vec::new(),
+            modules: HashMap::new(),
+            native_modules: natives::NATIVE_MODULES.iter().copied().collect(),
+            native_namespaces: HashMap::new(),
+            handlers: Vec::new(),
+            thrown: None,
+            current: 0,
+            stack: Vec::new(),
+            builtins: HashMap::new(),
+            main: Rc::new(Module::new(None)),
+            script_path: None,
+            natives: NativeState::new(interrupt.clone()),
+            packages: HashSet::new(),
+            json_trace: None,
+            trace: TraceOptions::default(),
+            executed: 0,
+            profile: None,
+            visualizer: None,
+            strict_numbers: false,
+            compiler_options: CompilerOptions::default(),
+            chunk_cache: None,
+            fuel: None,
+            stack_size: None,
+            frame_limit: DEFAULT_FRAME_LIMIT,
+            interrupt,
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+        };
+        for (name, function) in natives::NATIVES {
+            vm.define_context_native(name, *function);
+        }
+        vm
+    }
+
+    /// Runs the prelude, the part of the standard library written in Lox,
+    /// and defines its globals like the natives, unless one of the same
+    /// name is already defined. It runs in a VM of its own, so the fuel
+    /// limit, tracing and profiling of this one don't apply to it.
+    fn load_prelude(&mut self) {
+        // The prelude uses `throw` and `for`-`in` loops
+        #[cfg(feature = "extensions")]
+        {
+            const PRELUDE: &str = include_str!("prelude.lox");
+
+            let mut prelude = Self::without_prelude();
+            let chunk = Compiler::with_options(PRELUDE, CompilerOptions::default())
+                .compile()
+                .expect("The prelude compiles");
+            prelude.interpret_chunk(chunk).expect("The prelude runs");
+
+            for (name, value) in prelude.main.globals.borrow().iter() {
+                if let Value::Function(function) = value {
+                    assign_module(function, &prelude.main);
+                }
+                self.builtins.entry(name.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+
+    pub fn builder() -> VMBuilder {
+        VMBuilder::new()
+    }
+
+    /// Where program output goes, stdout by default.
+    pub fn set_output(&mut self, output: Box<dyn io::Write>) {
+        self.output = output;
+    }
+
+    /// Where compile and runtime errors are reported, stderr by default.
+    pub fn set_error_output(&mut self, error_output: Box<dyn io::Write>) {
+        self.error_output = error_output;
+    }
+
+    /// Limit the value stack to `size` slots, past which pushing is a
+    /// runtime error. `None` lets it grow without bound.
+    pub fn set_stack_size(&mut self, size: Option<usize>) {
+        self.stack_size = size;
+        if let Some(size) = size {
+            self.stack.reserve(size.saturating_sub(self.stack.len()));
+        }
+    }
+
+    /// Limit calls to nesting `limit` deep, the script itself included,
+    /// past which calling is a "Stack overflow." runtime error. Defaults to
+    /// 64.
+    pub fn set_frame_limit(&mut self, limit: usize) {
+        self.frame_limit = limit;
+    }
+
+    /// Defines a global variable holding `value`, replacing any previous
+    /// definition. Imported modules see it too.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.main.globals.borrow_mut().remove(name);
+        self.builtins.insert(name.into(), value);
+    }
+
+    /// Path of the script given to `interpret`, so that its imports are
+    /// resolved relative to it. Without one they are resolved from the
+    /// working directory.
+    pub fn set_script_path(&mut self, path: Option<PathBuf>) {
+        self.script_path = path;
+    }
+
+    /// Defines a global function implemented in Rust.
+    ///
+    /// ```
+    /// use m2_rslox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.define_native("double", |args| match args {
+    ///     [Value::Int(val)] => Ok(Value::Int(val * 2)),
+    ///     [Value::Number(val)] => Ok(Value::Number(val * 2.0)),
+    ///     _ => Err("Expected a number.".to_owned()),
+    /// });
+    /// vm.set_error_output(Box::new(std::io::sink()));
+    ///
+    /// assert!(vm.interpret("print double(21);").is_ok());
+    /// assert!(vm.interpret("print double(nil);").is_err());
+    /// ```
+    pub fn define_native(&mut self, name: &str, function: NativeFn) {
+        self.define_global(name, Value::NativeFn(Native::Plain(function)));
+    }
+
+    /// Like [`VM::define_native`], for a function that also takes the
+    /// [`NativeContext`] the natives of the VM share.
+    pub fn define_context_native(&mut self, name: &str, function: ContextNativeFn) {
+        self.define_global(name, Value::NativeFn(Native::WithContext(function)));
+    }
+
+    /// Defines the natives of `package`, unless a package of the same name
+    /// is already installed.
+    pub fn install(&mut self, package: &dyn NativePackage) {
+        if self.packages.insert(package.name().to_owned()) {
+            package.register(self);
+        }
+    }
+
+    /// Whether a package of that name has been installed with
+    /// [`VM::install`].
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.packages.contains(name)
+    }
+
+    /// Seeds the generator behind `random` and `randomInt`, so the numbers
+    /// they return are the same on every run. A new VM seeds it from the
+    /// system clock.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.natives.seed(seed);
+    }
+
+    /// Starts recording the inputs natives read from outside the VM, such
+    /// as the clock or standard input, until taken with `take_recording`.
+    /// Seeding with [`VM::set_seed`] afterwards isn't recorded.
+    pub fn start_recording(&mut self) {
+        self.natives.start_recording();
+    }
+
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.natives.take_recording()
+    }
+
+    /// Seeds the random number generator as it was when `recording` started
+    /// and has natives return the inputs it holds instead of reading them,
+    /// so the run it was recorded from can be repeated exactly.
+    pub fn replay(&mut self, recording: Recording) {
+        self.natives.replay(recording);
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Limit each `interpret` call to executing at most `fuel` instructions,
+    /// after which it fails with a runtime error. `None` removes the limit.
+    pub fn set_fuel(&mut self, fuel: Option<usize>) {
+        self.fuel = fuel;
+    }
+
+    pub fn compiler_options(&self) -> &CompilerOptions {
+        &self.compiler_options
+    }
+
+    /// Options used to compile the source given to `interpret`.
+    pub fn set_compiler_options(&mut self, options: CompilerOptions) {
+        self.compiler_options = options;
+    }
+
+    /// Look up compiled chunks for the source given to `interpret` in
+    /// `cache` before compiling it, and store newly compiled ones there.
+    pub fn set_chunk_cache(&mut self, cache: Option<ChunkCache>) {
+        self.chunk_cache = cache;
+    }
+
+    /// In strict numeric mode, arithmetic that produces an infinity or NaN
+    /// from finite operands, or that can't represent an integer result
+    /// exactly, is a runtime error instead of silently continuing.
+    pub fn set_strict_numbers(&mut self, strict: bool) {
+        self.strict_numbers = strict;
+    }
+
+    /// Call `visualizer` with the machine state after every instruction,
+    /// e.g. to render a step-by-step animation of the execution.
+    pub fn set_visualizer(&mut self, visualizer: Option<Visualizer>) {
+        self.visualizer = visualizer;
+    }
+
+    /// Defines the natives that reach outside the VM, such as `readFile`.
+    /// They are left out by default, so scripts from untrusted sources can
+    /// only affect the host through their output.
+    pub fn enable_io(&mut self) {
+        for (name, function) in natives::IO_NATIVES {
+            self.define_context_native(name, *function);
+        }
+        self.native_modules.insert("io", natives::IO_NATIVES);
+    }
+
+    /// Start collecting per-line execution statistics, accumulated across
+    /// calls to `interpret` until taken with `take_profile`.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::new);
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    pub fn trace_options(&self) -> &TraceOptions {
+        &self.trace
+    }
+
+    pub fn set_trace_options(&mut self, options: TraceOptions) {
+        self.trace = options;
+    }
+
+    /// Emit one JSON object per executed instruction into `writer`,
+    /// or stop tracing when given `None`.
+    pub fn set_json_trace(&mut self, writer: Option<Box<dyn io::Write>>) {
+        self.json_trace = writer;
     }
 
     pub fn interpret(&mut self, source: &str) -> Result<(), InterpretResult> {
-        let mut compiler = Compiler::new(source);
+        // Printing the code needs the compiler to run
+        let cached = self.chunk_cache.as_ref()
+            .filter(|_| !self.compiler_options.print_code)
+            .and_then(|cache| cache.load(source, &self.compiler_options));
+        if let Some(chunk) = cached {
+            return self.interpret_chunk(chunk);
+        }
+
+        let mut compiler = Compiler::with_options(source, self.compiler_options.clone());
 
         match compiler.compile() {
             Ok(chunk) => {
-                self.chunk = Some(chunk);
-                self.ip = 0;
+                if let Some(cache) = self.chunk_cache.as_ref() {
+                    cache.store(source, &self.compiler_options, &chunk);
+                }
+                self.interpret_chunk(chunk)
+            },
+            Err(err) => {
+                for diagnostic in err.diagnostics {
+                    let _ = writeln!(self.error_output, "{}", diagnostic);
+                }
+                Err(InterpretResult::CompileError)
             },
-            Err(_) => return Err(InterpretResult::CompileError),
         }
-
-        self.run()
     }
 
-    pub fn run(&mut self) -> Result<(), InterpretResult> {
-        if self.chunk().code().len() == 0 {
+    /// Runs an already compiled chunk, e.g. one built by the assembler.
+    pub fn interpret_chunk(&mut self, chunk: Chunk) -> Result<(), InterpretResult> {
+        if chunk.is_empty() {
             return Ok(());
         }
+        self.interrupt.0.store(false, Ordering::Relaxed);
+
+        let script = Rc::new(Function { chunk, ..Function::new(None) });
+        self.push(Value::Function(script.clone()))?;
+        self.frames.push(CallFrame { function: script, ip: 0, slots: 0 });
+
+        let result = self.run();
+
+        if let Some(profile) = self.profile.as_mut() {
+            profile.finish();
+        }
+
+        if let Some(trace) = self.json_trace.as_mut() {
+            let _ = trace.flush();
+        }
+        let _ = self.output.flush();
+
+        result
+    }
+
+    fn run(&mut self) -> Result<(), InterpretResult> {
+        self.executed = 0;
         loop {
-            let ip = self.ip;
-            self.ip += 1;
-            let instruction: &OpCode = &self.chunk().code()[ip];
+            let frame = self.frame();
+            let (ip, function) = (frame.ip, frame.function.clone());
+            let frame_count = self.frames.len();
+            let stack_before = self.stack.len();
+            let done = match self.step() {
+                Err(InterpretResult::RuntimeError) if self.thrown.is_some() => {
+                    self.catch();
+                    continue;
+                },
+                result => result?,
+            };
+
+            // Calls and returns also move the frame's slots
+            if cfg!(debug_assertions) && self.frames.len() == frame_count {
+                let instruction = &function.chunk.code()[ip];
+                let effect = self.stack.len() as isize - stack_before as isize;
+                debug_assert_eq!(
+                    effect, instruction.stack_effect(),
+                    "Unexpected stack effect of {:?} at {:04}", instruction, ip
+                );
+            }
+
+            if let Some(visualizer) = self.visualizer.as_mut() {
+                let chunk = &function.chunk;
+                let instruction = &chunk.code()[ip];
+                let globals = function.module.get().unwrap_or(&self.main).globals.borrow();
+
+                let global = match instruction {
+                    OpCode::DefineGlobal(id) | OpCode::SetGlobal(id) => {
+                        match &chunk.constants()[*id as usize] {
+                            Value::String(name) => globals.get_key_value(name)
+                                .map(|(name, value)| (&**name, value)),
+                            _ => None,
+                        }
+                    },
+                    _ => None,
+                };
+
+                visualizer(&MachineState {
+                    step: self.executed,
+                    offset: ip,
+                    instruction,
+                    line: chunk.lines()[ip],
+                    ip: self.frames.last().map_or(ip + 1, |frame| frame.ip),
+                    stack: &self.stack,
+                    global,
+                });
+            }
+
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one instruction, returning whether execution has finished.
+    fn step(&mut self) -> Result<bool, InterpretResult> {
+        let ip = self.frame().ip;
+        // Reported against the instruction that got us here
+        if ip >= self.chunk().len() {
+            return Err(self.internal_error("Ran past the end of the chunk"));
+        }
+        self.current = ip;
+        self.frame_mut().ip += 1;
+        self.executed += 1;
 
-            if cfg!(feature = "debug_trace_execution") {
+        // Neither can be caught, so scripts can't keep running past them
+        if self.fuel.is_some_and(|fuel| self.executed > fuel) {
+            self.report_error("Execution ran out of fuel.");
+            return Err(InterpretResult::RuntimeError);
+        }
+
+        if self.interrupt.0.swap(false, Ordering::Relaxed) {
+            self.report_error("Interrupted.");
+            return Err(InterpretResult::Interrupted);
+        }
+
+        let function = &self.frames.last().unwrap().function;
+
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(function.chunk.lines()[ip]);
+        }
+
+        if let Some(trace) = self.json_trace.as_mut() {
+            let _ = write_json_trace(trace, &function.chunk, ip, &self.stack);
+        }
+
+        let instruction: &OpCode = &function.chunk.code()[ip];
+
+        if self.trace.should_trace(instruction, function.name(), self.executed) {
+            if self.trace.level >= TraceLevel::Stack {
                 let stack_str: String = self.stack.iter()
                     .map(|elem| format!("[{}]", elem))
                     .collect();
 
                 eprintln!("   Stack: {stack_str}");
-                disassemble_instruction(&self.chunk(), ip)
             }
+            disassemble_instruction(self.chunk(), ip)
+        }
 
-            match instruction {
-                OpCode::Return => {
-                    eprintln!("{}", self.pop());
-                    return Ok(())
-                },
-                OpCode::Negate => {
-                    if let Value::Number(_) = self.peek(0) {
-                        let val = self.pop();
-                        self.push(-val);
-                    }
-                    else {
-                        self.runtime_error("Operand must be a number");
+        match instruction {
+            OpCode::Print => {
+                let value = self.pop()?;
+                let _ = writeln!(self.output, "{}", value);
+            },
+            OpCode::GetLocal(slot) => {
+                let slot = self.frame().slots + *slot as usize;
+                let Some(value) = self.stack.get(slot) else {
+                    return Err(self.internal_error("Local slot out of range"));
+                };
+                self.push(value.clone())?;
+            },
+            OpCode::SetLocal(slot) => {
+                let slot = self.frame().slots + *slot as usize;
+                let value = self.peek()?.clone();
+                let Some(local) = self.stack.get_mut(slot) else {
+                    return Err(self.internal_error("Local slot out of range"));
+                };
+                *local = value;
+            },
+            OpCode::Jump(distance) => {
+                let distance = *distance as usize;
+                self.frame_mut().ip += distance;
+            },
+            OpCode::JumpIfFalse(distance) => {
+                let distance = *distance as usize;
+                if self.peek()?.is_falsey() {
+                    self.frame_mut().ip += distance;
+                }
+            },
+            OpCode::ForIter(distance) => {
+                let distance = *distance as usize;
+                let state = self.pop()?;
+                let iterable = self.peek()?.clone();
+                match iterable.iterate(&state) {
+                    Ok(Some((element, state))) => {
+                        self.push(state)?;
+                        self.push(element)?;
+                    },
+                    Ok(None) => {
+                        self.push(state)?;
+                        self.push(Value::Nil)?;
+                        self.frame_mut().ip += distance;
+                    },
+                    Err(err) => {
+                        self.runtime_error(&err.to_string());
                         return Err(InterpretResult::RuntimeError);
-                    }
-                },
-                OpCode::Constant(id) => {
-                    let const_val = self.read_constant(*id as usize);
+                    },
+                }
+            },
+            OpCode::Loop(distance) => {
+                let Some(target) = self.frame().ip.checked_sub(*distance as usize) else {
+                    return Err(self.internal_error("Loop before the start of the chunk"));
+                };
+                self.frame_mut().ip = target;
+            },
+            OpCode::PushHandler(distance) => {
+                let target = self.frame().ip + *distance as usize;
+                self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    stack_height: self.stack.len(),
+                    target,
+                });
+            },
+            OpCode::PopHandler => {
+                if self.handlers.pop().is_none() {
+                    return Err(self.internal_error("No handler to pop"));
+                }
+            },
+            OpCode::Throw => {
+                let value = self.pop()?;
+                self.throw(value);
+                return Err(InterpretResult::RuntimeError);
+            },
+            OpCode::Call(arg_count) => {
+                let arg_count = *arg_count as usize;
+                let Some(callee) = self.stack.len().checked_sub(arg_count + 1) else {
+                    return Err(self.internal_error("Stack underflow"));
+                };
+                self.call_value(self.stack[callee].clone(), arg_count)?;
+            },
+            OpCode::BuildList(count) => {
+                let Some(start) = self.stack.len().checked_sub(*count as usize) else {
+                    return Err(self.internal_error("Stack underflow"));
+                };
+                let elements = self.stack.split_off(start);
+                self.push(Value::List(Rc::new(RefCell::new(elements))))?;
+            },
+            OpCode::BuildMap(count) => {
+                let Some(start) = self.stack.len().checked_sub(2 * *count as usize) else {
+                    return Err(self.internal_error("Stack underflow"));
+                };
+                let mut entries = self.stack.split_off(start).into_iter();
+                let mut map = IndexMap::with_capacity(*count as usize);
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    map.insert(key, value);
+                }
+                self.push(Value::Map(Rc::new(RefCell::new(map))))?;
+            },
+            OpCode::GetIndex => {
+                let index = self.pop()?;
+                let target = self.pop()?;
+                self.push_result(target.get_index(&index))?;
+            },
+            OpCode::SetIndex => {
+                let value = self.pop()?;
+                let index = self.pop()?;
+                let target = self.pop()?;
+                let result = target.set_index(index, value.clone());
+                self.push_result(result.map(|_| value))?;
+            },
+            OpCode::Unpack(count) => {
+                let count = *count as usize;
+                let value = self.pop()?;
+                let result = value.unpack(count);
+                self.push_values(result)?;
+            },
+            OpCode::UnpackMap(count) => {
+                let Some(start) = self.stack.len().checked_sub(*count as usize + 1) else {
+                    return Err(self.internal_error("Stack underflow"));
+                };
+                let keys = self.stack.split_off(start + 1);
+                let value = self.pop()?;
+                let result = value.unpack_keys(&keys);
+                self.push_values(result)?;
+            },
+            OpCode::Slice => {
+                let end = self.pop()?;
+                let start = self.pop()?;
+                let target = self.pop()?;
+                self.push_result(target.slice(&start, &end))?;
+            },
+            OpCode::Return => {
+                let result = self.pop()?;
+                let frame = self.frames.pop().unwrap();
+                self.stack.truncate(frame.slots);
+                // Returning from inside `try` blocks leaves them
+                while self.handlers.last().is_some_and(|handler| handler.frames > self.frames.len()) {
+                    self.handlers.pop();
+                }
 
-                    self.push(const_val.clone());
-                },
-                OpCode::Nil => self.push(Value::Nil),
-                OpCode::True => self.push(Value::Bool(true)),
-                OpCode::False => self.push(Value::Bool(false)),
-                OpCode::Equal => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    self.push(Value::Bool(a == b));
-                }
-                OpCode::Greater => {
-                    self.binary_cmp(cmp::PartialOrd::gt)?
-                },
-                OpCode::Less => {
-                    self.binary_cmp(cmp::PartialOrd::lt)?
-                },
-                OpCode::Add => self.binary_op(ops::Add::add)?,
-                OpCode::Substract => self.binary_op(ops::Sub::sub)?,
-                OpCode::Multiply => self.binary_op(ops::Mul::mul)?,
-                OpCode::Divide => self.binary_op(ops::Div::div)?,
-                OpCode::Not => {
-                    let val = self.pop();
-                    self.push(!val);
+                if self.frames.is_empty() {
+                    return Ok(true);
+                }
+                // A module's script results in its namespace
+                let result = if self.imports.last().is_some_and(|import| import.frames > self.frames.len()) {
+                    self.finish_import()
+                }
+                else {
+                    result
+                };
+                self.push(result)?;
+            },
+            OpCode::Negate => {
+                let val = self.pop()?;
+                let operand = self.strict_numbers.then(|| val.clone());
+                let result = val.checked_neg();
+
+                if let (Some(operand), Ok(value)) = (operand, &result) {
+                    self.check_strict("-", &[operand], value)?;
+                }
+                self.push_result(result)?
+            },
+            OpCode::Constant(id) => {
+                let const_val = self.read_constant(*id as usize);
+
+                self.push(const_val.clone())?;
+            },
+            OpCode::Nil => self.push(Value::Nil)?,
+            OpCode::True => self.push(Value::Bool(true))?,
+            OpCode::False => self.push(Value::Bool(false))?,
+            OpCode::Pop => {
+                self.pop()?;
+            },
+            OpCode::DefineGlobal(id) => {
+                let name = self.global_name(*id)?;
+                let value = self.pop()?;
+                self.module().globals.borrow_mut().insert(name, value);
+            },
+            OpCode::GetGlobal(id) => {
+                let name = self.global_name(*id)?;
+                let value = self.module().globals.borrow().get(&name)
+                    .or_else(|| self.builtins.get(&name))
+                    .cloned();
+                let Some(value) = value else {
+                    return Err(self.undefined_variable(&name));
+                };
+                self.push(value)?;
+            },
+            OpCode::SetGlobal(id) => {
+                let name = self.global_name(*id)?;
+                // Assignment leaves the value on the stack, since it's an
+                // expression
+                let value = self.pop()?;
+                let module = self.module().clone();
+                let mut globals = module.globals.borrow_mut();
+                if let Some(global) = globals.get_mut(&name) {
+                    *global = value.clone();
+                }
+                // Assigning to a builtin shadows it in this module only
+                else if self.builtins.contains_key(&name) {
+                    globals.insert(name, value.clone());
                 }
+                else {
+                    drop(globals);
+                    return Err(self.undefined_variable(&name));
+                }
+                drop(globals);
+                self.push(value)?;
+            },
+            OpCode::Import(id) => {
+                let Value::String(path) = self.read_constant(*id as usize).clone() else {
+                    return Err(self.internal_error("Module path is not a string"));
+                };
+                self.import(&path)?;
+            },
+            OpCode::Equal => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(Value::Bool(a == b))?;
             }
+            OpCode::Greater => self.binary_cmp(Value::greater)?,
+            OpCode::GreaterEqual => self.binary_cmp(Value::greater_equal)?,
+            OpCode::Less => self.binary_cmp(Value::less)?,
+            OpCode::LessEqual => self.binary_cmp(Value::less_equal)?,
+            OpCode::Add => self.binary_op("+", Value::checked_add)?,
+            OpCode::Substract => self.binary_op("-", Value::checked_sub)?,
+            OpCode::Multiply => self.binary_op("*", Value::checked_mul)?,
+            OpCode::Divide => self.binary_op("/", Value::checked_div)?,
+            OpCode::Modulo => self.binary_op("%", Value::checked_rem)?,
+            OpCode::Range => self.binary_op("..", Value::range)?,
+            OpCode::BitAnd => self.binary_op("&", Value::bit_and)?,
+            OpCode::BitOr => self.binary_op("|", Value::bit_or)?,
+            OpCode::BitXor => self.binary_op("^", Value::bit_xor)?,
+            OpCode::ShiftLeft => self.binary_op("<<", Value::shift_left)?,
+            OpCode::ShiftRight => self.binary_op(">>", Value::shift_right)?,
+            OpCode::BitNot => {
+                let val = self.pop()?;
+                self.push_result(val.bit_not())?
+            },
+            OpCode::Not => {
+                let val = self.pop()?;
+                self.push(!val)?;
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), InterpretResult> {
+        match callee {
+            Value::Function(function) => self.call(function, arg_count),
+            Value::NativeFn(native) => {
+                let args_start = self.stack.len() - arg_count;
+                let mut context = NativeContext::new(&mut self.natives, &self.frames);
+                let result = native.call(&mut context, &self.stack[args_start..]);
+                if let Some(code) = self.natives.exit_code.take() {
+                    self.reset_stack();
+                    return Err(InterpretResult::Exit(code));
+                }
+                match result {
+                    Ok(result) => {
+                        self.stack.truncate(args_start - 1);
+                        self.push(result)
+                    },
+                    Err(message) => {
+                        self.runtime_error(&message);
+                        Err(InterpretResult::RuntimeError)
+                    },
+                }
+            },
+            _ => {
+                self.runtime_error("Can only call functions and classes.");
+                Err(InterpretResult::RuntimeError)
+            },
+        }
+    }
+
+    fn call(&mut self, function: Rc<Function>, mut arg_count: usize) -> Result<(), InterpretResult> {
+        let arity = function.arity as usize;
+        if function.variadic && arg_count >= arity {
+            // The rest parameter gets the arguments past the others
+            let rest = self.stack.split_off(self.stack.len() - (arg_count - arity));
+            self.push(Value::List(Rc::new(RefCell::new(rest))))?;
+            arg_count = arity + 1;
+        }
+        else if function.variadic {
+            self.runtime_error(&format!(
+                "Expected at least {} arguments but got {}.", arity, arg_count
+            ));
+            return Err(InterpretResult::RuntimeError);
+        }
+        else if arg_count != arity {
+            self.runtime_error(&format!(
+                "Expected {} arguments but got {}.", arity, arg_count
+            ));
+            return Err(InterpretResult::RuntimeError);
+        }
+        if self.frames.len() >= self.frame_limit {
+            self.runtime_error("Stack overflow.");
+            return Err(InterpretResult::RuntimeError);
         }
+
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame { function, ip: 0, slots });
+        Ok(())
+    }
+
+    /// The module whose code the innermost frame is running.
+    fn module(&self) -> &Rc<Module> {
+        self.frames.last()
+            .and_then(|frame| frame.function.module.get())
+            .unwrap_or(&self.main)
+    }
+
+    /// Pushes the namespace of the module at `path` if it already ran, or
+    /// calls its script otherwise. Relative paths are resolved from the
+    /// directory of the file importing it, or the working directory if the
+    /// main script has no path.
+    fn import(&mut self, path: &str) -> Result<(), InterpretResult> {
+        if let Some(name) = path.strip_prefix("std:") {
+            return self.import_natives(name);
+        }
+
+        let importer = self.module().path.as_deref().or(self.script_path.as_deref());
+        let resolved = match importer.and_then(Path::parent) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        let canonical = match fs::canonicalize(resolved) {
+            Ok(canonical) => canonical,
+            Err(err) => {
+                self.runtime_error(&format!("Could not import '{}': {}.", path, err));
+                return Err(InterpretResult::RuntimeError);
+            },
+        };
+        if let Some(namespace) = self.modules.get(&canonical) {
+            return self.push(namespace.clone());
+        }
+        if self.imports.iter().any(|import| import.module.path.as_ref() == Some(&canonical)) {
+            self.runtime_error(&format!("Circular import of '{}'.", path));
+            return Err(InterpretResult::RuntimeError);
+        }
+
+        let source = match fs::read_to_string(&canonical) {
+            Ok(source) => source,
+            Err(err) => {
+                self.runtime_error(&format!("Could not import '{}': {}.", path, err));
+                return Err(InterpretResult::RuntimeError);
+            },
+        };
+        let chunk = match Compiler::with_options(&source, self.compiler_options.clone()).compile() {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                for diagnostic in err.diagnostics {
+                    let _ = writeln!(self.error_output, "{}", diagnostic);
+                }
+                self.runtime_error(&format!("Could not compile '{}'.", path));
+                return Err(InterpretResult::RuntimeError);
+            },
+        };
+
+        let module = Rc::new(Module::new(Some(canonical)));
+        let script = Rc::new(Function { chunk, ..Function::new(None) });
+        assign_module(&script, &module);
+        self.push(Value::Function(script.clone()))?;
+        self.call(script, 0)?;
+        self.imports.push(Import { module, frames: self.frames.len() });
+        Ok(())
+    }
+
+    /// Pushes the namespace of the natives imported as `"std:name"`: a map
+    /// from their names to the functions, built on its first import.
+    fn import_natives(&mut self, name: &str) -> Result<(), InterpretResult> {
+        let Some((&name, &natives)) = self.native_modules.get_key_value(name) else {
+            self.runtime_error(&format!("No native module named '{}'.", name));
+            return Err(InterpretResult::RuntimeError);
+        };
+        let namespace = self.native_namespaces.entry(name)
+            .or_insert_with(|| {
+                let functions = natives.iter()
+                    .map(|(name, function)| (
+                        Value::String((*name).into()),
+                        Value::NativeFn(Native::WithContext(*function)),
+                    ))
+                    .collect();
+                Value::Map(Rc::new(RefCell::new(functions)))
+            })
+            .clone();
+        self.push(namespace)
+    }
+
+    /// Records the namespace of the module whose script just returned: the
+    /// globals it defined, sorted by name.
+    fn finish_import(&mut self) -> Value {
+        let import = self.imports.pop().unwrap();
+
+        let mut globals: Vec<_> = import.module.globals.borrow().iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let namespace = globals.into_iter()
+            .map(|(name, value)| (Value::String(name), value))
+            .collect();
+
+        let namespace = Value::Map(Rc::new(RefCell::new(namespace)));
+        let path = import.module.path.clone().unwrap();
+        self.modules.insert(path, namespace.clone());
+        namespace
     }
 
     fn reset_stack(&mut self) {
         self.stack.clear();
+        self.frames.clear();
+        self.imports.clear();
+        self.handlers.clear();
+        self.thrown = None;
     }
 
+    /// Throws the message of a runtime error as a string. Callers stop
+    /// executing the instruction with `InterpretResult::RuntimeError`.
     fn runtime_error(&mut self, message: &str) {
-        eprintln!("{}", message);
-        eprintln!("[line {}] in script", self.chunk.as_ref().unwrap().lines()[self.ip - 1]);
+        self.throw(Value::String(message.into()));
+    }
+
+    /// Sends `value` to the innermost handler, which `run` jumps to, or
+    /// reports it as an error if there is none.
+    fn throw(&mut self, value: Value) {
+        if self.handlers.is_empty() {
+            self.report_error(&value.to_string());
+        }
+        else {
+            self.thrown = Some(value);
+        }
+    }
+
+    /// Unwinds to the innermost handler and passes it the thrown value.
+    fn catch(&mut self) {
+        let handler = self.handlers.pop().unwrap();
+        let value = self.thrown.take().unwrap();
+
+        self.frames.truncate(handler.frames);
+        self.imports.retain(|import| import.frames <= handler.frames);
+        self.stack.truncate(handler.stack_height);
+        self.frame_mut().ip = handler.target;
+        self.stack.push(value);
+    }
+
+    /// Reports the error with a trace of the calls in progress, innermost
+    /// first.
+    fn report_error(&mut self, message: &str) {
+        let _ = writeln!(self.error_output, "{}", message);
+
+        for (depth, frame) in self.frames.iter().enumerate().rev() {
+            // Callers are stopped right after their `Call`
+            let offset = if depth == self.frames.len() - 1 {
+                self.current
+            }
+            else {
+                frame.ip - 1
+            };
+            let line = frame.function.chunk.line_at(offset);
+
+            let _ = match &frame.function.name {
+                Some(name) => writeln!(self.error_output, "[line {}] in {}()", line, name),
+                None => writeln!(self.error_output, "[line {}] in script", line),
+            };
+        }
+        self.reset_stack();
+    }
+
+    /// Reports a VM bug along with the offending instruction, leaving the
+    /// VM in a usable state for the next `interpret` call.
+    fn internal_error(&mut self, message: &str) -> InterpretResult {
+        let instruction = instruction_to_string(self.chunk(), self.current);
+        let _ = writeln!(self.error_output, "Internal error: {}", message);
+        let _ = write!(self.error_output, "{}", instruction);
         self.reset_stack();
+        InterpretResult::InternalError
     }
 
     fn read_constant(&self, id: usize) -> &Value {
         &self.chunk().constants()[id]
     }
 
-    fn binary_op(&mut self, op_func: fn(Value, Value) -> Value) -> Result<(), InterpretResult> {
-        match (self.peek(0), self.peek(1)) {
-            (Value::Number(_), Value::Number(_)) => {
-                let b = self.pop();
-                let a = self.pop();
-                
-                let result = op_func(a, b);
-                self.push(result);
-                Ok(())
-            },
-            _ => {
-                self.runtime_error("Operands must be numbers");
-                Err(InterpretResult::RuntimeError)
-            }
+    fn global_name(&mut self, id: u8) -> Result<Rc<str>, InterpretResult> {
+        match self.read_constant(id as usize) {
+            Value::String(name) => Ok(name.clone()),
+            _ => Err(self.internal_error("Global name is not a string")),
         }
     }
 
-    fn binary_cmp(&mut self, cmp_func: fn(&Value, &Value) -> bool) -> Result<(), InterpretResult> {
-        match (self.peek(0), self.peek(1)) {
-            (Value::Number(_), Value::Number(_)) => {
-                let b = self.pop();
-                let a = self.pop();
-                
-                let result = cmp_func(&a, &b);
-                self.push(Value::Bool(result));
-                Ok(())
-            },
-            _ => {
-                self.runtime_error("Operands must be numbers");
+    fn undefined_variable(&mut self, name: &str) -> InterpretResult {
+        self.runtime_error(&format!("Undefined variable '{}'.", name));
+        InterpretResult::RuntimeError
+    }
+
+    fn binary_op(
+        &mut self, operator: &'static str, op_func: fn(Value, Value) -> Result<Value, TypeError>
+    ) -> Result<(), InterpretResult> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+
+        let operands = self.strict_numbers.then(|| [a.clone(), b.clone()]);
+        let result = op_func(a, b);
+
+        if let (Some(operands), Ok(value)) = (operands, &result) {
+            self.check_strict(operator, &operands, value)?;
+        }
+        self.push_result(result)
+    }
+
+    fn binary_cmp(&mut self, cmp_func: fn(&Value, &Value) -> Result<bool, TypeError>) -> Result<(), InterpretResult> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+
+        let result = cmp_func(&a, &b).map(Value::Bool);
+        self.push_result(result)
+    }
+
+    fn check_strict(
+        &mut self, operator: &'static str, operands: &[Value], result: &Value
+    ) -> Result<(), InterpretResult> {
+        value::check_strict(operator, operands, result).map_err(|err| {
+            self.runtime_error(&err.to_string());
+            InterpretResult::RuntimeError
+        })
+    }
+
+    /// Pushes the result of a value operation, turning an error into a
+    /// runtime error.
+    fn push_result<E: fmt::Display>(&mut self, result: Result<Value, E>) -> Result<(), InterpretResult> {
+        match result {
+            Ok(value) => self.push(value),
+            Err(err) => {
+                self.runtime_error(&err.to_string());
                 Err(InterpretResult::RuntimeError)
             }
         }
     }
 
-    fn push(&mut self, value: Value) {
+    /// Like `push_result`, for an operation producing several values.
+    fn push_values(&mut self, result: Result<Vec<Value>, IndexError>) -> Result<(), InterpretResult> {
+        let values = result.map_err(|err| {
+            self.runtime_error(&err.to_string());
+            InterpretResult::RuntimeError
+        })?;
+        for value in values {
+            self.push(value)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, value: Value) -> Result<(), InterpretResult> {
+        if self.stack_size.is_some_and(|size| self.stack.len() >= size) {
+            self.runtime_error("Stack overflow.");
+            return Err(InterpretResult::RuntimeError);
+        }
         self.stack.push(value);
+        Ok(())
     }
 
-    fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap()
+    fn peek(&mut self) -> Result<&Value, InterpretResult> {
+        if self.stack.is_empty() {
+            return Err(self.internal_error("Stack underflow"));
+        }
+        Ok(self.stack.last().unwrap())
+    }
+
+    fn pop(&mut self) -> Result<Value, InterpretResult> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.internal_error("Stack underflow")),
+        }
     }
 
-    fn peek(&self, distance: usize) -> &Value {
-        self.stack.iter().rev().nth(distance).unwrap()
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("No function running in VM")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("No function running in VM")
     }
 
     fn chunk(&self) -> &Chunk {
-        self.chunk.as_ref().expect("No chunk loaded in VM")
+        &self.frame().function.chunk
+    }
+}
+
+/// Makes a module's script and the functions in its code use the module's
+/// globals.
+fn assign_module(function: &Function, module: &Rc<Module>) {
+    let _ = function.module.set(module.clone());
+    for constant in function.chunk.constants() {
+        if let Value::Function(function) = constant {
+            assign_module(function, module);
+        }
+    }
+}
+
+/// Configures a [`VM`] before it is created.
+///
+/// Every option defaults to what [`VM::new`] uses, and most can still be
+/// changed afterwards through the matching `VM::set_*` method.
+///
+/// ```
+/// use m2_rslox::VM;
+///
+/// let mut vm = VM::builder()
+///     .stack_size(64)
+///     .fuel(1_000)
+///     .output(std::io::sink())
+///     .build();
+/// assert!(vm.interpret("print 1 + 2;").is_ok());
+/// ```
+pub struct VMBuilder {
+    vm: VM,
+    prelude: bool,
+    recording: bool,
+}
+
+impl Default for VMBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VMBuilder {
+    pub fn new() -> Self {
+        Self { vm: VM::without_prelude(), prelude: true, recording: false }
+    }
+
+    pub fn build(mut self) -> VM {
+        if self.prelude {
+            self.vm.load_prelude();
+        }
+        // After seeding, so the recording starts from the seed
+        if self.recording {
+            self.vm.start_recording();
+        }
+        self.vm
+    }
+
+    /// Whether to define the functions of the prelude, such as `assert` and
+    /// `len`, which [`VM::new`] does. Minimal embeds can leave them out.
+    /// Natives defined through the builder take precedence over them.
+    pub fn prelude(mut self, enabled: bool) -> Self {
+        self.prelude = enabled;
+        self
     }
-}
\ No newline at end of file
+
+    /// Maximum number of values on the stack, see [`VM::set_stack_size`].
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.vm.set_stack_size(Some(size));
+        self
+    }
+
+    /// Maximum depth of nested calls, see [`VM::set_frame_limit`].
+    pub fn frame_limit(mut self, limit: usize) -> Self {
+        self.vm.set_frame_limit(limit);
+        self
+    }
+
+    /// Instruction budget per `interpret` call, see [`VM::set_fuel`].
+    pub fn fuel(mut self, fuel: usize) -> Self {
+        self.vm.set_fuel(Some(fuel));
+        self
+    }
+
+    pub fn output(mut self, output: impl io::Write + 'static) -> Self {
+        self.vm.set_output(Box::new(output));
+        self
+    }
+
+    pub fn error_output(mut self, error_output: impl io::Write + 'static) -> Self {
+        self.vm.set_error_output(Box::new(error_output));
+        self
+    }
+
+    pub fn compiler_options(mut self, options: CompilerOptions) -> Self {
+        self.vm.set_compiler_options(options);
+        self
+    }
+
+    pub fn chunk_cache(mut self, cache: ChunkCache) -> Self {
+        self.vm.set_chunk_cache(Some(cache));
+        self
+    }
+
+    pub fn strict_numbers(mut self, strict: bool) -> Self {
+        self.vm.set_strict_numbers(strict);
+        self
+    }
+
+    pub fn trace_options(mut self, options: TraceOptions) -> Self {
+        self.vm.set_trace_options(options);
+        self
+    }
+
+    pub fn json_trace(mut self, writer: impl io::Write + 'static) -> Self {
+        self.vm.set_json_trace(Some(Box::new(writer)));
+        self
+    }
+
+    pub fn visualizer(mut self, visualizer: Visualizer) -> Self {
+        self.vm.set_visualizer(Some(visualizer));
+        self
+    }
+
+    /// See [`VM::set_script_path`].
+    pub fn script_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.vm.set_script_path(Some(path.into()));
+        self
+    }
+
+    /// See [`VM::set_seed`].
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.vm.set_seed(seed);
+        self
+    }
+
+    /// Defines a global function, see [`VM::define_native`].
+    pub fn native(mut self, name: &str, function: NativeFn) -> Self {
+        self.vm.define_native(name, function);
+        self
+    }
+
+    /// See [`VM::define_context_native`].
+    pub fn context_native(mut self, name: &str, function: ContextNativeFn) -> Self {
+        self.vm.define_context_native(name, function);
+        self
+    }
+
+    /// See [`VM::install`].
+    pub fn install(mut self, package: &dyn NativePackage) -> Self {
+        self.vm.install(package);
+        self
+    }
+
+    pub fn profiling(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.vm.enable_profiling();
+        }
+        self
+    }
+
+    /// Records the inputs of the VM from when it is built, see
+    /// [`VM::start_recording`].
+    pub fn recording(mut self, enabled: bool) -> Self {
+        self.recording = enabled;
+        self
+    }
+
+    /// See [`VM::replay`].
+    pub fn replay(mut self, recording: Recording) -> Self {
+        self.vm.replay(recording);
+        self
+    }
+
+    /// See [`VM::enable_io`].
+    pub fn io(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.vm.enable_io();
+        }
+        self
+    }
+}
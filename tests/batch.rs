@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use m2_rslox::batch;
+use m2_rslox::CompilerOptions;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("m2-rslox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn lox() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_m2-rslox"))
+}
+
+#[test]
+fn compiles_a_tree_and_reports_each_failure() {
+    let dir = temp_dir("batch");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("a.lox"), "print 1 + 2;").unwrap();
+    fs::write(dir.join("nested/b.lox"), "print 3 *").unwrap();
+    fs::write(dir.join("nested/c.lox"), "print (4").unwrap();
+    fs::write(dir.join("nested/d.lox"), "print -5;").unwrap();
+    fs::write(dir.join("notes.txt"), "not lox").unwrap();
+
+    let output = lox().arg("compile").arg(&dir).output().unwrap();
+    assert_eq!(output.status.code(), Some(65));
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("b.lox: [line 1] Error at end"), "{}", stderr);
+    assert!(stderr.contains("c.lox: [line 1] Error at end"), "{}", stderr);
+
+    assert!(!dir.join("nested/b.loxc").exists());
+    assert!(!dir.join("notes.loxc").exists());
+
+    for (compiled, expected) in [("a.loxc", "3\n"), ("nested/d.loxc", "-5\n")] {
+        let output = lox().arg(dir.join(compiled)).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn results_keep_the_input_order() {
+    let dir = temp_dir("batch-order");
+    let paths: Vec<PathBuf> = (0..50)
+        .map(|i| {
+            let path = dir.join(format!("{:02}.lox", i));
+            let source = if i % 7 == 0 { "1 +".to_owned() } else { format!("print {};", i) };
+            fs::write(&path, source).unwrap();
+            path
+        })
+        .collect();
+    let missing = dir.join("missing.lox");
+
+    let mut inputs = paths.clone();
+    inputs.push(missing.clone());
+    let results = batch::compile_files(&inputs, &CompilerOptions::default());
+
+    let result_paths: Vec<_> = results.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(result_paths, inputs);
+    for (i, file) in results.iter().enumerate() {
+        assert_eq!(file.result.is_ok(), i % 7 != 0 && i < 50, "{}", file.path.display());
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+use std::rc::Rc;
+
+use m2_rslox::bytecode::{self, FORMAT_VERSION};
+use m2_rslox::chunk::MAX_FUNCTION_DEPTH;
+use m2_rslox::{Chunk, DecodeError, Function, Value};
+
+/// Encoded bytecode for a script holding `depth` functions, each the only
+/// constant of the one outside it, all without code.
+fn nested_bytecode(depth: usize) -> Vec<u8> {
+    let mut bytes = b"LOXC".to_vec();
+    bytes.push(FORMAT_VERSION);
+    for _ in 0..depth {
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&[5, 0, 0]);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.push(b'f');
+    }
+    // The innermost constant table, then the code of every chunk
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    for _ in 0..=depth {
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+    }
+    bytes
+}
+
+/// A script holding `depth` functions, each the only constant of the one
+/// outside it, all without code.
+fn nested_chunk(depth: usize) -> Chunk {
+    let mut chunk = Chunk::new();
+    for _ in 0..depth {
+        let mut function = Function::new(Some("f".into()));
+        function.chunk = chunk;
+        chunk = Chunk::new();
+        chunk.add_constant(Value::Function(Rc::new(function)));
+    }
+    chunk
+}
+
+#[test]
+fn decoding_limits_function_nesting() {
+    assert!(bytecode::decode(&nested_bytecode(MAX_FUNCTION_DEPTH)).is_ok());
+    let too_deep = bytecode::decode(&nested_bytecode(MAX_FUNCTION_DEPTH + 1));
+    assert!(matches!(too_deep, Err(DecodeError::TooDeep)));
+    // Deep enough to overflow the stack without the limit
+    let far_too_deep = bytecode::decode(&nested_bytecode(1_000_000));
+    assert!(matches!(far_too_deep, Err(DecodeError::TooDeep)));
+}
+
+#[test]
+fn verifying_limits_function_nesting() {
+    assert!(nested_chunk(MAX_FUNCTION_DEPTH).verify().is_ok());
+    let err = nested_chunk(MAX_FUNCTION_DEPTH + 1).verify().unwrap_err();
+    assert!(err.message.ends_with("Functions nested more than 256 deep"), "{}", err);
+}
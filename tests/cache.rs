@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use m2_rslox::cache::ChunkCache;
+use m2_rslox::{bytecode, Compiler, CompilerOptions, DecodeError};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("m2-rslox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs a script through the CLI, returning its stdout.
+fn run(script: &Path, cache_dir: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_m2-rslox"))
+        .arg("--cache-dir")
+        .arg(cache_dir)
+        .arg(script)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn cache_entries(cache_dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(cache_dir)
+        .map(|entries| entries.map(|entry| entry.unwrap().path()).collect())
+        .unwrap_or_default()
+}
+
+// Printing the code makes the VM compile the source even on a hit
+#[cfg(not(feature = "debug_print_code"))]
+#[test]
+fn cache_hit_skips_compilation() {
+    let dir = temp_dir("cache-hit");
+    let script = dir.join("script.lox");
+    let cache_dir = dir.join("cache");
+    fs::write(&script, "print 1 + 2;").unwrap();
+
+    assert_eq!(run(&script, &cache_dir), "3\n");
+    let entries = cache_entries(&cache_dir);
+    assert_eq!(entries.len(), 1);
+
+    // Swap in different code for the same source: if the second run
+    // compiled the source it would print 3 again
+    let other = Compiler::new("print 40 + 2;").compile().unwrap();
+    ChunkCache::new(&cache_dir).store("print 1 + 2;", &CompilerOptions::default(), &other);
+    assert_eq!(cache_entries(&cache_dir), entries);
+
+    assert_eq!(run(&script, &cache_dir), "42\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_entries_are_recompiled() {
+    let dir = temp_dir("cache-corrupt");
+    let script = dir.join("script.lox");
+    let cache_dir = dir.join("cache");
+    fs::write(&script, "print 2 * 3;").unwrap();
+
+    assert_eq!(run(&script, &cache_dir), "6\n");
+    let entries = cache_entries(&cache_dir);
+    let valid = fs::read(&entries[0]).unwrap();
+
+    // Truncated, a constant load past the end of the table, and an entry
+    // stored for another source with the same hash
+    let code = bytecode::encode(&Compiler::new("print 2 * 3;").compile().unwrap());
+    let source = &valid[..valid.len() - code.len()];
+    let mut out_of_range = bytecode::encode(&Compiler::new("print 1;").compile().unwrap());
+    // Header (5), constant table (4 + 9), instruction count (4), opcode (1)
+    assert_eq!(out_of_range[23], 0);
+    out_of_range[23] = 1;
+    assert!(matches!(bytecode::decode(&out_of_range), Err(DecodeError::Invalid(_))));
+    let out_of_range = [source, &out_of_range].concat();
+    let mut other_source = valid.clone();
+    other_source[8] = b'.';
+    for bytes in [&valid[..valid.len() - 1], &out_of_range, &other_source] {
+        fs::write(&entries[0], bytes).unwrap();
+        assert_eq!(run(&script, &cache_dir), "6\n");
+        assert_eq!(fs::read(&entries[0]).unwrap(), valid);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn changed_source_misses() {
+    let dir = temp_dir("cache-miss");
+    let script = dir.join("script.lox");
+    let cache_dir = dir.join("cache");
+
+    fs::write(&script, "print 1;").unwrap();
+    assert_eq!(run(&script, &cache_dir), "1\n");
+    fs::write(&script, "print 2;").unwrap();
+    assert_eq!(run(&script, &cache_dir), "2\n");
+    assert_eq!(cache_entries(&cache_dir).len(), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn least_recently_used_entries_are_evicted() {
+    let dir = temp_dir("cache-evict");
+    let cache = ChunkCache::new(dir.join("cache")).max_entries(2);
+    let options = CompilerOptions::default();
+    let store = |source| {
+        cache.store(source, &options, &Compiler::new(source).compile().unwrap());
+        // Keep the modification times apart
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    store("print 1;");
+    store("print 2;");
+    assert!(cache.load("print 1;", &options).is_some());
+    thread::sleep(Duration::from_millis(20));
+    store("print 3;");
+
+    assert_eq!(cache_entries(cache.dir()).len(), 2);
+    assert!(cache.load("print 1;", &options).is_some());
+    assert!(cache.load("print 2;", &options).is_none());
+    assert!(cache.load("print 3;", &options).is_some());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("m2-rslox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn lox() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_m2-rslox"))
+}
+
+#[test]
+fn assembly_is_verified_before_running() {
+    let dir = temp_dir("cli-asm");
+    fs::write(dir.join("good.asm"), "Constant String(\"hi\")\nPrint\nNil\nReturn\n").unwrap();
+    fs::write(dir.join("underflow.asm"), "Add\nNil\nReturn\n").unwrap();
+
+    let output = lox().arg("asm").arg(dir.join("good.asm")).output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+
+    let output = lox().arg("asm").arg(dir.join("underflow.asm")).output().unwrap();
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Add underflows the stack"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disasm_follows_the_std_option() {
+    let dir = temp_dir("cli-disasm-std");
+    let path = dir.join("remainder.lox");
+    fs::write(&path, "print 7 % 2;").unwrap();
+
+    let output = lox().args(["disasm", "--std=lox"]).arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'%' is not part of standard Lox"));
+
+    let output = lox().args(["disasm", "--std=lox", "--diff"]).arg(&path).arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(65));
+
+    let output = lox().args(["disasm", "--source", "--std=extended"]).arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Modulo"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn repl_disassembles_the_next_input() {
+    let mut child = lox()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b":dis\nprint 1;\nprint 2;\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\n");
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(errors.matches("== input ==").count(), 1);
+    assert!(errors.contains(";; 1: print 1;"));
+    assert!(errors.contains("Print"));
+}
+
+#[test]
+fn unreadable_files_exit_with_an_io_error() {
+    let dir = temp_dir("cli-missing");
+    let missing = dir.join("missing.lox");
+
+    for args in [&["disasm"][..], &["disasm", "--source"], &["disasm", "--cfg"], &["asm"], &[]] {
+        let output = lox().args(args).arg(&missing).output().unwrap();
+        assert_eq!(output.status.code(), Some(74), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("Could not read"), "{:?}", args);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,217 @@
+//! Golden-file conformance runner for the craftinginterpreters test corpus.
+//!
+//! Point `LOX_TEST_DIR` at the `test/` directory of a craftinginterpreters
+//! checkout and run `cargo test --test conformance -- --nocapture` to see
+//! which suites pass. Suites listed in `tests/conformance/passing.txt` must
+//! keep passing. Without `LOX_TEST_DIR` the runner does nothing.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use m2_rslox::{InterpretResult, VM};
+
+/// Suites that don't follow the expectation format or exercise the
+/// reference implementations' internals.
+const SKIPPED_SUITES: &[&str] = &["benchmark", "scanning", "expressions"];
+
+/// Enough to finish every non-benchmark test while stopping runaway loops.
+const FUEL: usize = 10_000_000;
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.borrow())
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    errors: Vec<String>,
+    exit_code: i32,
+}
+
+fn parse_expectations(source: &str) -> Expectations {
+    let mut expected = Expectations::default();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+
+        if let Some(output) = comment_after(line, "// expect: ") {
+            expected.output.push(output.to_owned());
+        }
+        else if let Some(message) = comment_after(line, "// expect runtime error: ") {
+            // Only the innermost frame of the stack trace is checked
+            expected.errors.push(message.to_owned());
+            expected.errors.push(format!("[line {}]", line_number));
+            expected.exit_code = 70;
+        }
+        else if let Some(error) = comment_after(line, "// Error") {
+            expected.errors.push(format!("[line {}] Error{}", line_number, error));
+            expected.exit_code = 65;
+        }
+        else if let Some(rest) = comment_after(line, "// [") {
+            // `[line N] Error...`, optionally tagged for one implementation
+            let rest = match rest.strip_prefix("java ") {
+                Some(_) => continue,
+                None => rest.strip_prefix("c ").unwrap_or(rest),
+            };
+            if rest.starts_with("line ") {
+                expected.errors.push(format!("[{}", rest));
+                expected.exit_code = 65;
+            }
+        }
+    }
+
+    expected
+}
+
+fn comment_after<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    line.find(marker).map(|pos| &line[pos + marker.len()..])
+}
+
+fn run(source: &str) -> (Vec<String>, Vec<String>, i32) {
+    let output = SharedBuffer::default();
+    let errors = SharedBuffer::default();
+
+    let mut vm = VM::new();
+    vm.set_fuel(Some(FUEL));
+    vm.set_output(Box::new(output.clone()));
+    vm.set_error_output(Box::new(errors.clone()));
+
+    let exit_code = match vm.interpret(source) {
+        Ok(()) => 0,
+        Err(InterpretResult::CompileError) => 65,
+        Err(_) => 70,
+    };
+
+    (output.lines(), errors.lines(), exit_code)
+}
+
+fn check(path: &Path) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let expected = parse_expectations(&source);
+    let (output, mut errors, exit_code) = run(&source);
+
+    if exit_code == 70 && errors.len() >= 2 {
+        errors.truncate(2);
+        if let Some(end) = errors[1].find(']') {
+            errors[1].truncate(end + 1);
+        }
+    }
+
+    if output != expected.output {
+        return Err(format!("expected output {:?}, got {:?}", expected.output, output));
+    }
+    if errors != expected.errors {
+        return Err(format!("expected errors {:?}, got {:?}", expected.errors, errors));
+    }
+    if exit_code != expected.exit_code {
+        return Err(format!("expected exit code {}, got {}", expected.exit_code, exit_code));
+    }
+    Ok(())
+}
+
+fn collect_tests(dir: &Path, tests: &mut Vec<PathBuf>) {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("{}: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_tests(&path, tests);
+        }
+        else if path.extension().is_some_and(|ext| ext == "lox") {
+            tests.push(path);
+        }
+    }
+}
+
+fn suite_of(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap();
+    match relative.parent().and_then(|parent| parent.components().next()) {
+        Some(suite) => suite.as_os_str().to_string_lossy().into_owned(),
+        None => "(root)".to_owned(),
+    }
+}
+
+fn passing_suites() -> Vec<String> {
+    let list = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/passing.txt");
+    fs::read_to_string(list)
+        .unwrap()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn conformance() {
+    let Some(root) = std::env::var_os("LOX_TEST_DIR") else {
+        eprintln!("LOX_TEST_DIR is not set, skipping the conformance suite");
+        return;
+    };
+    let root = PathBuf::from(root);
+
+    let mut tests = Vec::new();
+    collect_tests(&root, &mut tests);
+
+    // suite -> (passed, total)
+    let mut results: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+
+    for path in &tests {
+        let suite = suite_of(&root, path);
+        if SKIPPED_SUITES.contains(&suite.as_str()) {
+            continue;
+        }
+        if fs::read_to_string(path).is_ok_and(|source| source.contains("// nontest")) {
+            continue;
+        }
+
+        let counts = results.entry(suite).or_default();
+        counts.1 += 1;
+        match check(path) {
+            Ok(()) => counts.0 += 1,
+            Err(reason) => eprintln!("FAIL {}: {}", path.display(), reason),
+        }
+    }
+
+    let (mut passed, mut total) = (0, 0);
+    eprintln!();
+    for (suite, (suite_passed, suite_total)) in &results {
+        let mark = if suite_passed == suite_total { "ok" } else { "  " };
+        eprintln!("{} {:<24} {:>4}/{}", mark, suite, suite_passed, suite_total);
+        passed += suite_passed;
+        total += suite_total;
+    }
+    eprintln!("{} of {} tests passed", passed, total);
+
+    let regressions: Vec<_> = passing_suites()
+        .into_iter()
+        .filter(|suite| results.get(suite).is_none_or(|(passed, total)| passed != total))
+        .collect();
+    assert!(regressions.is_empty(), "suites no longer passing: {:?}", regressions);
+}
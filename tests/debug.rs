@@ -0,0 +1,32 @@
+use m2_rslox::{debug, Chunk, Compiler};
+
+fn compile(source: &str) -> Chunk {
+    Compiler::new(source).compile().unwrap()
+}
+
+#[test]
+fn diff_pairs_nested_functions_by_name() {
+    let old = compile("fun f() { fun g() { return \"a\"; } return g; } fun gone() {}");
+    let new = compile("fun f() { fun g() { return \"b\"; } return g; } fun came() {}");
+    let diff = debug::chunk_diff_to_string(&old, &new);
+
+    // Unchanged functions aren't changes in the enclosing chunk
+    assert!(diff.contains("\n  0000 0000 Constant(1) Function(<fn f>)\n"), "{}", diff);
+    assert!(diff.contains("\n== f: code ==\n  0000 0000 Constant(0) Function(<fn g>)\n"), "{}", diff);
+    assert!(diff.contains("\n== f.g: code ==\n- 0000      Constant(0) String(\"a\")\n+      0000 Constant(0) String(\"b\")\n"), "{}", diff);
+    assert!(diff.contains("\n== gone: code ==\n- 0000      Nil\n"), "{}", diff);
+    assert!(diff.contains("\n== came: code ==\n+      0000 Nil\n"), "{}", diff);
+}
+
+#[cfg(feature = "extensions")]
+#[test]
+fn cfg_has_a_cluster_per_function() {
+    let chunk = compile("fun f(xs) { for (x in xs) print x; } f([1]);");
+    let dot = debug::cfg_to_string(&chunk, "script");
+
+    assert!(dot.starts_with("digraph \"script\" {\n"), "{}", dot);
+    assert!(dot.contains("subgraph cluster0 {\n        label=\"script\";\n"), "{}", dot);
+    assert!(dot.contains("subgraph cluster1 {\n        label=\"f\";\n"), "{}", dot);
+    assert!(dot.contains("c1b1 -> c1b2 [label=\"next\"];"), "{}", dot);
+    assert!(dot.contains("c1b1 -> c1b3 [label=\"done\"];"), "{}", dot);
+}
@@ -0,0 +1,54 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use m2_rslox::{InterpretResult, VM};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Calls nest four deep, counting the script.
+const NESTED: &str = "
+    fun c() { return 1; }
+    fun b() { return c(); }
+    fun a() { return b(); }
+    print a();
+";
+
+#[test]
+fn calls_within_the_frame_limit_run() {
+    let mut vm = VM::builder().frame_limit(4).output(io::sink()).build();
+    assert_eq!(vm.interpret(NESTED), Ok(()));
+}
+
+#[test]
+fn calls_past_the_frame_limit_overflow() {
+    let errors = SharedBuffer::default();
+    let mut vm = VM::builder()
+        .frame_limit(3)
+        .output(io::sink())
+        .error_output(errors.clone())
+        .build();
+    assert_eq!(vm.interpret(NESTED), Err(InterpretResult::RuntimeError));
+    assert!(String::from_utf8_lossy(&errors.0.borrow()).starts_with("Stack overflow."));
+
+    vm.set_frame_limit(4);
+    assert_eq!(vm.interpret(NESTED), Ok(()));
+}
+
+#[test]
+fn unbounded_recursion_overflows_by_default() {
+    let mut vm = VM::builder().error_output(io::sink()).build();
+    assert_eq!(vm.interpret("fun f() { return f(); } f();"), Err(InterpretResult::RuntimeError));
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "extensions")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("m2-rslox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs a script through the CLI from `cwd`, returning its stdout.
+fn run(script: &Path, cwd: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_m2-rslox"))
+        .arg(script)
+        .current_dir(cwd)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn modules_have_their_own_globals() {
+    let dir = temp_dir("modules-globals");
+    fs::write(dir.join("main.lox"), r#"
+        var name = "main";
+        import m from "module.lox";
+        print name;
+        print m["name"];
+        print m["greet"]();
+    "#).unwrap();
+    fs::write(dir.join("module.lox"), r#"
+        var name = "module";
+        fun greet() { return "hello from " + name; }
+        print type(clock());
+    "#).unwrap();
+
+    assert_eq!(run(&dir.join("main.lox"), &dir), "number\nmain\nmodule\nhello from module\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn imports_are_relative_to_the_importing_file() {
+    let dir = temp_dir("modules-paths");
+    fs::create_dir_all(dir.join("app/lib")).unwrap();
+    fs::write(dir.join("app/main.lox"), r#"
+        import a from "lib/a.lox";
+        print a["b"]["value"];
+    "#).unwrap();
+    fs::write(dir.join("app/lib/a.lox"), r#"import b from "b.lox";"#).unwrap();
+    fs::write(dir.join("app/lib/b.lox"), "var value = 42;").unwrap();
+
+    // From a directory other than the script's
+    assert_eq!(run(Path::new("app/main.lox"), &dir), "42\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn natives_are_imported_by_namespace() {
+    let dir = temp_dir("modules-natives");
+    fs::write(dir.join("main.lox"), r#"
+        import math from "std:math";
+        import m from "module.lox";
+        print math["sqrt"](16);
+        print math["max"](1, 2.5);
+        print m["math"] == math;
+        print type(m["io"]["cwd"]);
+        try { import nothing from "std:nothing"; } catch (e) { print e; }
+    "#).unwrap();
+    fs::write(dir.join("module.lox"), r#"
+        import math from "std:math";
+        import io from "std:io";
+    "#).unwrap();
+
+    assert_eq!(
+        run(&dir.join("main.lox"), &dir),
+        "4\n2.5\ntrue\nfunction\nNo native module named 'nothing'.\n"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use m2_rslox::{Value, VM};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The lines a script prints.
+fn run(source: &str) -> Vec<String> {
+    let output = SharedBuffer::default();
+    let mut vm = VM::builder().output(output.clone()).build();
+    vm.interpret(source).unwrap();
+    let output = output.0.borrow();
+    String::from_utf8_lossy(&output).lines().map(str::to_owned).collect()
+}
+
+#[test]
+fn nan_is_not_equal_to_itself() {
+    let lines = run("
+        var nan = 0.0 / 0.0;
+        print nan == nan;
+        print nan != nan;
+        print nan == 1.0;
+        var same = nan;
+        print same == nan;
+    ");
+    assert_eq!(lines, ["false", "true", "false", "false"]);
+}
+
+#[test]
+fn comparisons_with_nan_are_false() {
+    let lines = run("
+        var nan = 0.0 / 0.0;
+        print nan < 1.0;
+        print nan <= 1.0;
+        print nan > 1.0;
+        print nan >= 1.0;
+        print 1 < nan;
+        print 1 >= nan;
+        print nan <= nan;
+        print nan >= nan;
+    ");
+    assert_eq!(lines, ["false"; 8]);
+}
+
+#[test]
+fn nan_and_infinities_print() {
+    let lines = run("
+        print 0.0 / 0.0;
+        print -(0.0 / 0.0);
+        print 1.0 / 0.0;
+        print -1.0 / 0.0;
+    ");
+    assert_eq!(lines, ["NaN", "NaN", "inf", "-inf"]);
+}
+
+#[test]
+fn is_nan_and_is_finite() {
+    let lines = run("
+        print isNan(0.0 / 0.0);
+        print isNan(1.5);
+        print isNan(3);
+        print isFinite(1.0 / 0.0);
+        print isFinite(0.0 / 0.0);
+        print isFinite(1.5);
+        print isFinite(3);
+    ");
+    assert_eq!(lines, ["true", "false", "false", "false", "false", "true", "true"]);
+}
+
+/// Prints a number and parses it back, checking that the bits survive.
+fn round_trip(val: f64) -> String {
+    let printed = Value::Number(val).to_string();
+    let parsed: f64 = printed.parse().unwrap();
+    assert_eq!(parsed.to_bits(), val.to_bits(), "{} printed as {}", val, printed);
+    printed
+}
+
+#[test]
+fn numbers_print_in_shortest_round_trip_form() {
+    assert_eq!(round_trip(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(round_trip(0.1), "0.1");
+    assert_eq!(round_trip(1.5), "1.5");
+    assert_eq!(round_trip(-0.0), "-0");
+    assert_eq!(round_trip(1e300), "1e300");
+    assert_eq!(round_trip(f64::MAX), "1.7976931348623157e308");
+    assert_eq!(round_trip(1.2345678901234569e23), "1.2345678901234569e23");
+    assert_eq!(round_trip(f64::MIN_POSITIVE), "2.2250738585072014e-308");
+    assert_eq!(round_trip(5e-324), "5e-324");
+    assert_eq!(round_trip(-2.5e-320), "-2.5e-320");
+}
+
+#[test]
+fn numbers_round_trip_across_magnitudes() {
+    let mut bits: u64 = 0x9e37_79b9_7f4a_7c15;
+    for _ in 0..10_000 {
+        bits = bits.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let val = f64::from_bits(bits);
+        if val.is_finite() {
+            round_trip(val);
+        }
+    }
+}
+
+#[test]
+fn printed_sums_round_trip() {
+    assert_eq!(run("print 0.1 + 0.2;"), ["0.30000000000000004"]);
+    assert_eq!(run("print 100000000000000000000.0 * 10;"), ["1e21"]);
+    assert_eq!(run("print 1.0 / 3;"), ["0.3333333333333333"]);
+}
+
+#[cfg(feature = "extensions")]
+#[test]
+fn bitwise_operands_are_truncated() {
+    let lines = run("
+        print 2.5 & 3;
+        print -2.5 | 0;
+        print 100000000000000000000.0 | 0;
+        print (0.0 / 0.0) ^ 1;
+        print ~0.5;
+    ");
+    assert_eq!(lines, ["2", "-2", "9223372036854775807", "1", "-1"]);
+}
+
+#[cfg(feature = "extensions")]
+#[test]
+fn long_shifts_shift_every_bit_out() {
+    let lines = run("
+        print 1 << 63;
+        print 1 << 64;
+        print 1 << -1;
+        print 8 >> 64;
+        print -8 >> 100;
+        print -8 >> -1;
+    ");
+    assert_eq!(lines, ["-9223372036854775808", "0", "0", "0", "-1", "-1"]);
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "extensions")]
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use m2_rslox::{Value, VM};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The lines a script prints.
+fn run(mut vm: VM, output: SharedBuffer, source: &str) -> Vec<String> {
+    vm.interpret(source).unwrap();
+    let output = output.0.borrow();
+    String::from_utf8_lossy(&output).lines().map(str::to_owned).collect()
+}
+
+#[test]
+fn prelude_functions_are_defined() {
+    let output = SharedBuffer::default();
+    let vm = VM::builder().output(output.clone()).build();
+    let lines = run(vm, output, "
+        print len([1, 2, 3]);
+        print len(\"four\");
+        print contains(0..10, 7);
+        print contains({\"a\": 1}, \"b\");
+        assert(true, \"unreachable\");
+        assertEqual([1, [2]], [1, [2]]);
+        try { assertEqual(1, 2); } catch (e) { print e; }
+        try { assert(false, \"failed\"); } catch (e) { print e; }
+    ");
+    assert_eq!(lines, ["3", "4", "true", "false", "Expected 2 but got 1.", "failed"]);
+}
+
+#[test]
+fn loading_the_prelude_uses_no_fuel() {
+    let output = SharedBuffer::default();
+    let vm = VM::builder().output(output.clone()).fuel(4).build();
+    assert_eq!(run(vm, output, "print 1;"), ["1"]);
+}
+
+#[test]
+fn prelude_can_be_left_out() {
+    let mut vm = VM::builder().prelude(false).error_output(io::sink()).build();
+    assert!(vm.interpret("len([]);").is_err());
+}
+
+#[test]
+fn natives_take_precedence_over_the_prelude() {
+    let output = SharedBuffer::default();
+    let vm = VM::builder()
+        .output(output.clone())
+        .native("len", |_| Ok(Value::Nil))
+        .build();
+    assert_eq!(run(vm, output, "print len([1]);"), ["nil"]);
+}
+
+#[test]
+fn scripts_can_shadow_the_prelude() {
+    let output = SharedBuffer::default();
+    let vm = VM::builder().output(output.clone()).build();
+    let lines = run(vm, output, "
+        fun len(x) { return -1; }
+        print len([1]);
+        print contains([1], 1);
+    ");
+    assert_eq!(lines, ["-1", "true"]);
+}
+
+#[test]
+fn map_filter_and_reduce_call_back_into_lox() {
+    let output = SharedBuffer::default();
+    let vm = VM::builder().output(output.clone()).build();
+    let lines = run(vm, output, "
+        var factor = 10;
+        fun scale(x) { return x * factor; }
+        print map([1, 2, 3], scale);
+        print filter(0..10, fun (x) { return x % 3 == 0; });
+        print reduce(\"abc\", fun (word, ch) { return ch + word; }, \"\");
+        print map([], scale);
+        factor = 2;
+        print reduce(map(1..4, scale), fun (a, b) { return a + b; }, 0);
+        try { map([1], fun (x) { throw \"from \" + str(x); }); } catch (e) { print e; }
+    ");
+    assert_eq!(lines, ["[10, 20, 30]", "[0, 3, 6, 9]", "cba", "[]", "12", "from 1"]);
+}
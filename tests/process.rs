@@ -0,0 +1,70 @@
+#![cfg(feature = "extensions")]
+
+use std::cell::RefCell;
+use std::env;
+use std::io;
+use std::rc::Rc;
+
+use m2_rslox::VM;
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The lines a script prints with I/O natives enabled.
+fn run(source: &str) -> Vec<String> {
+    let output = SharedBuffer::default();
+    let mut vm = VM::builder().output(output.clone()).io(true).build();
+    vm.interpret(source).unwrap();
+    let output = output.0.borrow();
+    String::from_utf8_lossy(&output).lines().map(str::to_owned).collect()
+}
+
+/// A string literal for the path of the interpreter's binary, which the
+/// tests run as a program.
+fn interpreter() -> String {
+    format!("{:?}", env!("CARGO_BIN_EXE_m2-rslox"))
+}
+
+#[test]
+fn cwd_is_the_current_directory() {
+    let dir = env::current_dir().unwrap();
+    assert_eq!(run("print cwd();"), [dir.to_str().unwrap()]);
+}
+
+#[test]
+fn exec_returns_status_and_output() {
+    let lines = run(&format!("
+        var result = exec({}, [\"disasm\"]);
+        print result[0];
+        print result[1] == \"\";
+        print result[2] != \"\";
+    ", interpreter()));
+    assert_eq!(lines, ["64", "true", "true"]);
+}
+
+#[test]
+fn exec_errors_are_runtime_errors() {
+    let mut vm = VM::builder().io(true).build();
+    assert!(vm.interpret("exec(\"m2-rslox-no-such-program\", []);").is_err());
+    assert!(vm.interpret(&format!("exec({}, [1]);", interpreter())).is_err());
+    assert!(vm.interpret(&format!("exec({}, \"disasm\");", interpreter())).is_err());
+}
+
+#[test]
+fn process_natives_need_io() {
+    let mut vm = VM::new();
+    assert!(vm.interpret("cwd();").is_err());
+    assert!(vm.interpret("exec(\"true\", []);").is_err());
+    assert!(vm.interpret("import io from \"std:io\";").is_err());
+}
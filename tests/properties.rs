@@ -0,0 +1,240 @@
+use proptest::prelude::*;
+use proptest::strategy::Union;
+
+use m2_rslox::{asm, bytecode, debug, Compiler, InterpretResult, VM};
+
+/// Whether the generators may use syntax that only exists with the
+/// `extensions` feature, which the compiler is built with by default.
+const EXTENSIONS: bool = cfg!(feature = "extensions");
+
+/// One of `standard`, or of `standard` and `extended` with extensions on.
+fn either<T: std::fmt::Debug + 'static>(
+    standard: Vec<BoxedStrategy<T>>, extended: Vec<BoxedStrategy<T>>
+) -> impl Strategy<Value = T> + Clone {
+    let mut strategies = standard;
+    if EXTENSIONS {
+        strategies.extend(extended);
+    }
+    Union::new(strategies)
+}
+
+/// Syntactically valid Lox expressions.
+fn expression() -> impl Strategy<Value = String> {
+    let literal = either(
+        vec![
+            any::<u32>().prop_map(|n| n.to_string()).boxed(),
+            (any::<u32>(), 0..1000u32).prop_map(|(int, frac)| format!("{int}.{frac}")).boxed(),
+            Just("true".to_string()).boxed(),
+            Just("false".to_string()).boxed(),
+            Just("nil".to_string()).boxed(),
+            prop_oneof![Just("a"), Just("b"), Just("c")].prop_map(str::to_string).boxed(),
+            "[a-z \n]{0,8}".prop_map(|s| format!("\"{s}\"")).boxed(),
+        ],
+        vec![
+            (1..1000u32, -400..400i32)
+                .prop_map(|(mantissa, exp)| format!("{mantissa}e{exp}"))
+                .boxed(),
+            "[1-9](_?[0-9]){0,6}(\\.[0-9](_?[0-9]){0,3})?".boxed(),
+            any::<u64>().prop_map(|n| format!("{n:#x}")).boxed(),
+            any::<u16>().prop_map(|n| format!("{n:#b}")).boxed(),
+            "([a-z \n]|\\\\[ntr0\\\\\"]){0,8}".prop_map(|s| format!("\"{s}\"")).boxed(),
+            "[a-z \\\\\n]{0,8}".prop_map(|s| format!("r\"{s}\"")).boxed(),
+        ],
+    );
+
+    literal.prop_recursive(6, 64, 2, |inner| {
+        let operator = either(
+            ["+", "-", "*", "/", "==", "!=", "<", "<=", ">", ">=", "and", "or"]
+                .map(|op| Just(op).boxed())
+                .to_vec(),
+            ["%", "&", "|", "^", "<<", ">>", ".."]
+                .map(|op| Just(op).boxed())
+                .to_vec(),
+        );
+        let unary = either(
+            vec![Just("-").boxed(), Just("!").boxed()],
+            vec![Just("~").boxed()],
+        );
+
+        either(
+            vec![
+                (inner.clone(), operator, inner.clone())
+                    .prop_map(|(lhs, op, rhs)| format!("{lhs} {op} {rhs}"))
+                    .boxed(),
+                (unary, inner.clone())
+                    .prop_map(|(op, operand)| format!("{op}{operand}"))
+                    .boxed(),
+                inner.clone().prop_map(|expr| format!("({expr})")).boxed(),
+            ],
+            vec![
+                (inner.clone(), inner.clone())
+                    .prop_map(|(target, index)| format!("({target})[{index}]"))
+                    .boxed(),
+                (inner.clone(), inner.clone(), inner.clone())
+                    .prop_map(|(target, start, end)| format!("({target})[{start}:{end}]"))
+                    .boxed(),
+                inner.clone().prop_map(|target| format!("({target})[:]")).boxed(),
+                prop::collection::vec(inner.clone(), 0..4)
+                    .prop_map(|elements| format!("[{}]", elements.join(", ")))
+                    .boxed(),
+                // Parenthesized, since a statement can't start with a map
+                prop::collection::vec((inner.clone(), inner), 0..4)
+                    .prop_map(|entries| {
+                        let entries: Vec<_> = entries.iter()
+                            .map(|(key, value)| format!("{key}: {value}"))
+                            .collect();
+                        format!("({{{}}})", entries.join(", "))
+                    })
+                    .boxed(),
+            ],
+        )
+    })
+}
+
+/// Syntactically valid Lox programs over the variables `a`, `b` and `c`,
+/// which may be used before they are defined and called whatever they hold.
+fn program() -> impl Strategy<Value = String> {
+    let name = prop_oneof![Just("a"), Just("b"), Just("c")];
+    let simple = either(
+        vec![
+            (name.clone(), expression())
+                .prop_map(|(name, expr)| format!("var {name} = {expr};"))
+                .boxed(),
+            name.clone().prop_map(|name| format!("var {name};")).boxed(),
+            (name.clone(), expression())
+                .prop_map(|(name, expr)| format!("{name} = {expr};"))
+                .boxed(),
+            (name.clone(), expression())
+                .prop_map(|(name, arg)| format!("{name}({arg});"))
+                .boxed(),
+            expression().prop_map(|expr| format!("print {expr};")).boxed(),
+            expression().prop_map(|expr| format!("{expr};")).boxed(),
+        ],
+        vec![
+            (name.clone(), name.clone(), expression())
+                .prop_map(|(first, second, expr)| format!("var {first}, {second} = {expr};"))
+                .boxed(),
+            (name.clone(), name.clone(), expression())
+                .prop_map(|(first, second, expr)| format!("var [{first}, {second}] = {expr};"))
+                .boxed(),
+            (name.clone(), expression())
+                .prop_map(|(name, expr)| format!("var {{{name}}} = {expr};"))
+                .boxed(),
+            (name.clone(), expression(), expression())
+                .prop_map(|(name, index, expr)| format!("{name}[{index}] = {expr};"))
+                .boxed(),
+            expression().prop_map(|expr| format!("throw {expr};")).boxed(),
+        ],
+    );
+
+    let statement = simple.prop_recursive(3, 32, 4, move |inner| {
+        let params = either(vec![Just("b").boxed()], vec![Just("b, ...c").boxed()]);
+        either(
+            vec![
+                (name.clone(), params, inner.clone())
+                    .prop_map(|(name, params, body)| format!("fun {name}({params}) {{ {body} }}"))
+                    .boxed(),
+                (name.clone(), inner.clone(), expression())
+                    .prop_map(|(name, body, result)| {
+                        format!("fun {name}(b) {{ {body} return {result}; }}")
+                    })
+                    .boxed(),
+                prop::collection::vec(inner.clone(), 0..4)
+                    .prop_map(|statements| format!("{{\n{}\n}}", statements.join("\n")))
+                    .boxed(),
+                (expression(), inner.clone())
+                    .prop_map(|(condition, body)| format!("while ({condition}) {{ {body} }}"))
+                    .boxed(),
+                (expression(), expression(), inner.clone())
+                    .prop_map(|(condition, increment, body)| {
+                        format!("for (var a = 0; {condition}; a = {increment}) {{ {body} }}")
+                    })
+                    .boxed(),
+            ],
+            vec![
+                (expression(), inner.clone())
+                    .prop_map(|(condition, body)| {
+                        format!("outer: while ({condition}) {{ {body} continue outer; }}")
+                    })
+                    .boxed(),
+                (name.clone(), expression(), inner.clone())
+                    .prop_map(|(name, iterable, body)| {
+                        format!("for ({name} in {iterable}) {{ {body} break; }}")
+                    })
+                    .boxed(),
+                (inner.clone(), expression())
+                    .prop_map(|(body, condition)| format!("do {{ {body} }} while ({condition});"))
+                    .boxed(),
+                (name.clone(), expression(), inner.clone())
+                    .prop_map(|(name, iterable, body)| format!("for ({name} in {iterable}) {{ {body} }}"))
+                    .boxed(),
+                (inner.clone(), inner.clone())
+                    .prop_map(|(body, handler)| format!("try {{ {body} }} catch (b) {{ {handler} }}"))
+                    .boxed(),
+            ],
+        )
+    });
+
+    prop::collection::vec(statement, 0..8).prop_map(|statements| statements.join("\n"))
+}
+
+proptest! {
+    #[test]
+    fn compiling_arbitrary_text_never_panics(source in "\\PC*") {
+        let _ = Compiler::new(&source).compile();
+    }
+
+    #[test]
+    fn valid_programs_compile(source in program()) {
+        // Redeclaring a local in the same block and reading one in its own
+        // initializer are the only errors the generator can produce
+        if let Err(err) = Compiler::new(&source).compile() {
+            prop_assert!(err.diagnostics.iter().all(|diagnostic| {
+                diagnostic.contains("Already a variable with this name")
+                    || diagnostic.contains("in its own initializer")
+            }), "{}", err);
+        }
+    }
+
+    #[test]
+    fn disassembly_round_trips_through_the_assembler(source in program()) {
+        let chunk = Compiler::new(&source).compile();
+        prop_assume!(chunk.is_ok());
+        let chunk = chunk.unwrap();
+        // The assembler has no syntax for function constants
+        prop_assume!(!source.contains("fun "));
+        let text = debug::chunk_to_string(&chunk, "script");
+
+        let assembled = asm::assemble(&text).unwrap();
+        prop_assert_eq!(debug::chunk_to_string(&assembled, "script"), text);
+    }
+
+    #[test]
+    fn bytecode_round_trips(source in program()) {
+        let chunk = Compiler::new(&source).compile();
+        prop_assume!(chunk.is_ok());
+        let chunk = chunk.unwrap();
+        let decoded = bytecode::decode(&bytecode::encode(&chunk)).unwrap();
+        prop_assert_eq!(
+            debug::chunk_to_string(&decoded, "script"),
+            debug::chunk_to_string(&chunk, "script")
+        );
+    }
+
+    #[test]
+    fn decoding_arbitrary_bytes_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+        let _ = bytecode::decode(&bytes);
+    }
+
+    #[test]
+    fn valid_programs_never_hit_internal_errors(source in program()) {
+        let mut vm = VM::builder()
+            .fuel(10_000)
+            .output(std::io::sink())
+            .error_output(std::io::sink())
+            .build();
+
+        let result = vm.interpret(&source);
+        prop_assert_ne!(result, Err(InterpretResult::InternalError));
+    }
+}
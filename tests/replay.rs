@@ -0,0 +1,92 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use m2_rslox::{InterpretResult, Recording, RecordingError, VM};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+const SCRIPT: &str = "
+    print nanos();
+    print random();
+    print now();
+    print random();
+";
+
+#[test]
+fn replaying_repeats_the_recorded_run() {
+    let output = SharedBuffer::default();
+    let mut vm = VM::builder().output(output.clone()).recording(true).build();
+    vm.interpret(SCRIPT).unwrap();
+    let recording = vm.take_recording().unwrap();
+    assert_eq!(recording.len(), 2);
+
+    let recording = Recording::decode(&recording.encode()).unwrap();
+    let replayed = SharedBuffer::default();
+    let mut vm = VM::builder().output(replayed.clone()).replay(recording).build();
+    vm.interpret(SCRIPT).unwrap();
+
+    assert_eq!(replayed.text(), output.text());
+}
+
+#[test]
+fn recording_starts_from_the_seed() {
+    let recording = VM::builder().recording(true).seed(42).build().take_recording().unwrap();
+
+    let seeded = SharedBuffer::default();
+    let mut vm = VM::builder().output(seeded.clone()).seed(42).build();
+    vm.interpret("print random();").unwrap();
+
+    let replayed = SharedBuffer::default();
+    let mut vm = VM::builder().output(replayed.clone()).replay(recording).build();
+    vm.interpret("print random();").unwrap();
+
+    assert_eq!(replayed.text(), seeded.text());
+}
+
+#[test]
+fn replaying_a_different_script_is_an_error() {
+    let mut vm = VM::builder().output(io::sink()).recording(true).build();
+    vm.interpret("print clock();").unwrap();
+    let recording = vm.take_recording().unwrap();
+
+    let errors = SharedBuffer::default();
+    let mut vm = VM::builder()
+        .output(io::sink())
+        .error_output(errors.clone())
+        .replay(recording)
+        .build();
+    assert_eq!(vm.interpret("print now();"), Err(InterpretResult::RuntimeError));
+    assert!(errors.text().contains("Expected an input for 'now' but the recording has one for 'clock'."));
+
+    assert_eq!(vm.interpret("print clock(); print clock();"), Err(InterpretResult::RuntimeError));
+    assert!(errors.text().contains("The recording has no more inputs for 'clock'."));
+}
+
+#[test]
+fn invalid_recordings_are_rejected() {
+    assert_eq!(Recording::decode(b"LOXC"), Err(RecordingError::BadMagic));
+    assert_eq!(Recording::decode(b"LOXR"), Err(RecordingError::UnexpectedEnd));
+
+    let mut bytes = VM::builder().recording(true).build().take_recording().unwrap().encode();
+    bytes.push(0);
+    assert_eq!(Recording::decode(&bytes), Err(RecordingError::TrailingBytes));
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "extensions")]
+
+use m2_rslox::{Compiler, Value};
+
+/// The value of a string literal.
+fn literal(source: &str) -> Result<Value, Vec<String>> {
+    let chunk = Compiler::new(&format!("print {};", source)).compile()
+        .map_err(|err| err.diagnostics)?;
+    Ok(chunk.constants()[0].clone())
+}
+
+#[test]
+fn regular_strings_process_escapes() {
+    assert_eq!(literal(r#""a\tb\nc""#), Ok(Value::String("a\tb\nc".into())));
+    assert_eq!(literal(r#""\\ \" \r \0""#), Ok(Value::String("\\ \" \r \0".into())));
+    assert_eq!(literal(r#""\u{41}\u{1F600}""#), Ok(Value::String("A\u{1F600}".into())));
+}
+
+#[test]
+fn raw_strings_keep_backslashes() {
+    assert_eq!(literal(r#"r"a\tb\d""#), Ok(Value::String(r"a\tb\d".into())));
+}
+
+#[test]
+fn unknown_escapes_are_kept() {
+    assert_eq!(literal(r#""\q""#), Ok(Value::String(r"\q".into())));
+    assert_eq!(literal(r#""C:\dir\file""#), Ok(Value::String(r"C:\dir\file".into())));
+    assert_eq!(literal(r#""\d+\.\\""#), Ok(Value::String(r"\d+\.\".into())));
+}
+
+#[test]
+fn invalid_unicode_escapes_are_errors() {
+    for (source, message) in [
+        (r#""\u{d800}""#, "Invalid unicode escape"),
+        (r#""\u{110000}""#, "Invalid unicode escape"),
+        (r#""\u41""#, "Invalid unicode escape"),
+    ] {
+        let diagnostics = literal(source).unwrap_err();
+        assert!(diagnostics[0].ends_with(message), "{:?}", diagnostics);
+    }
+}